@@ -0,0 +1,288 @@
+use std::io::Write;
+
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveTo,
+    event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    style::{Print, PrintStyledContent, Stylize},
+};
+
+use crate::{
+    client::board::{BoardCell, Canvas},
+    layout::Drawable,
+};
+
+const BRUSHES: [char; 6] = ['#', '*', '+', '.', 'o', 'x'];
+
+// A character canvas shared with everyone in the room
+pub struct Whiteboard {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    visible: bool,
+    // keys and the mouse go to the board instead of the chatbox
+    focused: bool,
+    canvas: Canvas,
+    cursor: (u16, u16),
+    // where Enter takes the cursor, like the start of a line of text
+    anchor: u16,
+    brush: usize,
+    // the last cell the mouse drew on, so that fast strokes don't leave gaps
+    last_mouse: Option<(u16, u16)>,
+}
+
+impl Whiteboard {
+    const PADDING: u16 = 1;
+
+    pub fn new(author: &str) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+            visible: false,
+            focused: false,
+            canvas: Canvas::new(author),
+            cursor: (0, 0),
+            anchor: 0,
+            brush: 0,
+            last_mouse: None,
+        }
+    }
+
+    pub fn resize(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.x = x;
+        self.y = y;
+        self.width = width;
+        self.height = height;
+
+        let (columns, rows) = self.inner_size();
+        self.cursor = (
+            self.cursor.0.min(columns.saturating_sub(1)),
+            self.cursor.1.min(rows.saturating_sub(1)),
+        );
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        if !visible {
+            self.focused = false;
+        }
+    }
+
+    pub fn focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.last_mouse = None;
+    }
+
+    fn inner_size(&self) -> (u16, u16) {
+        (
+            self.width.saturating_sub(2 * (Self::PADDING + 1)),
+            self.height.saturating_sub(2),
+        )
+    }
+
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let (columns, rows) = self.inner_size();
+        let x = (self.cursor.0 as i32 + dx).clamp(0, columns.saturating_sub(1) as i32);
+        let y = (self.cursor.1 as i32 + dy).clamp(0, rows.saturating_sub(1) as i32);
+        self.cursor = (x as u16, y as u16);
+    }
+
+    fn set(&mut self, (x, y): (u16, u16), character: Option<char>) -> Option<BoardCell> {
+        self.canvas.set(x, y, character)
+    }
+
+    // returns the edits that peers need to hear about
+    pub fn input(&mut self, key_event: &KeyEvent) -> Vec<BoardCell> {
+        let brush = Some(BRUSHES[self.brush]);
+        let drawing = key_event.modifiers.contains(KeyModifiers::SHIFT);
+        let direction = match key_event.code {
+            KeyCode::Up => Some((0, -1)),
+            KeyCode::Down => Some((0, 1)),
+            KeyCode::Left => Some((-1, 0)),
+            KeyCode::Right => Some((1, 0)),
+            _ => None,
+        };
+
+        let mut cells = Vec::new();
+        if let Some((dx, dy)) = direction {
+            // shift draws a line with the brush on the way
+            if drawing {
+                cells.extend(self.set(self.cursor, brush));
+            }
+            self.move_cursor(dx, dy);
+            if drawing {
+                cells.extend(self.set(self.cursor, brush));
+            }
+            self.anchor = self.cursor.0;
+            return cells;
+        }
+
+        match key_event.code {
+            KeyCode::Char(c)
+                if !key_event
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                let character = if c == ' ' { None } else { Some(c) };
+                cells.extend(self.set(self.cursor, character));
+                self.move_cursor(1, 0);
+            }
+            KeyCode::Backspace => {
+                self.move_cursor(-1, 0);
+                cells.extend(self.set(self.cursor, None));
+            }
+            KeyCode::Delete => cells.extend(self.set(self.cursor, None)),
+            KeyCode::Enter => {
+                self.cursor.0 = self.anchor;
+                self.move_cursor(0, 1);
+            }
+            KeyCode::Tab => self.brush = (self.brush + 1) % BRUSHES.len(),
+            _ => {}
+        }
+        cells
+    }
+
+    // the left button draws with the brush and the right one erases
+    pub fn mouse(&mut self, mouse_event: &MouseEvent) -> Vec<BoardCell> {
+        let (columns, rows) = self.inner_size();
+        let left = self.x + Self::PADDING + 1;
+        let top = self.y + 1;
+        if mouse_event.column < left
+            || mouse_event.row < top
+            || mouse_event.column >= left + columns
+            || mouse_event.row >= top + rows
+        {
+            self.last_mouse = None;
+            return Vec::new();
+        }
+        let position = (mouse_event.column - left, mouse_event.row - top);
+
+        let character =
+            match mouse_event.kind {
+                MouseEventKind::Down(MouseButton::Left)
+                | MouseEventKind::Drag(MouseButton::Left) => Some(BRUSHES[self.brush]),
+                MouseEventKind::Down(MouseButton::Right)
+                | MouseEventKind::Drag(MouseButton::Right) => None,
+                MouseEventKind::Up(_) => {
+                    self.last_mouse = None;
+                    return Vec::new();
+                }
+                _ => return Vec::new(),
+            };
+
+        let from = match mouse_event.kind {
+            MouseEventKind::Drag(_) => self.last_mouse.unwrap_or(position),
+            _ => position,
+        };
+        self.last_mouse = Some(position);
+        self.cursor = position;
+        self.anchor = position.0;
+
+        line(from, position)
+            .into_iter()
+            .filter_map(|cell| self.set(cell, character))
+            .collect()
+    }
+
+    // returns whether any of the edits changed the board
+    pub fn merge(&mut self, cells: Vec<BoardCell>) -> bool {
+        let mut changed = false;
+        for cell in cells {
+            changed |= self.canvas.merge(cell);
+        }
+        changed
+    }
+
+    pub fn cells(&self) -> Vec<BoardCell> {
+        self.canvas.cells()
+    }
+
+    pub fn to_text(&self) -> String {
+        self.canvas.to_text()
+    }
+}
+
+// the cells between two points, both included
+fn line(from: (u16, u16), to: (u16, u16)) -> Vec<(u16, u16)> {
+    let dx = to.0 as i32 - from.0 as i32;
+    let dy = to.1 as i32 - from.1 as i32;
+    let steps = dx.abs().max(dy.abs());
+    if steps == 0 {
+        return vec![to];
+    }
+
+    (0..=steps)
+        .map(|i| {
+            let x = from.0 as f32 + dx as f32 * i as f32 / steps as f32;
+            let y = from.1 as f32 + dy as f32 * i as f32 / steps as f32;
+            (x.round() as u16, y.round() as u16)
+        })
+        .collect()
+}
+
+impl Drawable for Whiteboard {
+    fn draw(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        if !self.visible {
+            return Ok(());
+        }
+
+        let (columns, rows) = self.inner_size();
+        for y in 0..rows {
+            let line = (0..columns)
+                .map(|x| self.canvas.get(x, y).unwrap_or(' '))
+                .collect::<Vec<char>>();
+            stdout.queue(MoveTo(self.x + Self::PADDING + 1, self.y + 1 + y))?;
+
+            if !self.focused || y != self.cursor.1 || self.cursor.0 >= columns {
+                stdout.queue(Print(line.iter().collect::<String>()))?;
+                continue;
+            }
+
+            let cursor = self.cursor.0 as usize;
+            stdout
+                .queue(Print(line[..cursor].iter().collect::<String>()))?
+                .queue(PrintStyledContent(line[cursor].reverse()))?
+                .queue(Print(line[cursor + 1..].iter().collect::<String>()))?;
+        }
+
+        stdout.flush()
+    }
+
+    fn x(&self) -> u16 {
+        self.x
+    }
+
+    fn y(&self) -> u16 {
+        self.y
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn title(&self) -> Option<String> {
+        if self.focused {
+            Some(format!(
+                "board · brush {} · Esc to chat",
+                BRUSHES[self.brush]
+            ))
+        } else {
+            Some(String::from("board"))
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 use crate::chat::{Chatbox, error::Error};
 
@@ -8,6 +8,21 @@ pub enum ChatboxCommand {
     Create,
     Join { room_id: String },
     Broadcast,
+    Stop,
+    Send { path: String },
+    Accept,
+    Reject,
+    ShareTerminal { command: Option<String> },
+    Unshare,
+    Grant { username: Option<String> },
+    Focus,
+    Board,
+    CloseBoard,
+    SaveBoard { path: String },
+    React { emoji: String },
+    Hand,
+    Stats,
+    Debug,
     Exit,
 }
 
@@ -23,9 +38,27 @@ const CREATE_COMMAND: &str = "/create";
 const JOIN_COMMAND: &str = "/join";
 const EXIT_COMMAND: &str = "/exit";
 const QUIT_COMMAND: &str = "/quit";
+const STATS_COMMAND: &str = "/stats";
+const DEBUG_COMMAND: &str = "/debug";
 
 // TODO: reconsider this?
 const BROADCAST_COMMAND: &str = "/broadcast";
+const STOP_COMMAND: &str = "/stop";
+const SEND_COMMAND: &str = "/send";
+const ACCEPT_COMMAND: &str = "/accept";
+const REJECT_COMMAND: &str = "/reject";
+const SHARE_TERMINAL_COMMAND: &str = "/share-terminal";
+const UNSHARE_COMMAND: &str = "/unshare";
+const GRANT_COMMAND: &str = "/grant";
+const FOCUS_COMMAND: &str = "/focus";
+const BOARD_COMMAND: &str = "/board";
+const REACT_COMMAND: &str = "/react";
+const HAND_COMMAND: &str = "/hand";
+
+// long enough for emoji made of several code points, e.g. skin tones and flags
+const MAX_REACTION_CHARS: usize = 8;
+// Alt+1 to Alt+5
+const REACTION_KEYS: [&str; 5] = ["👍", "😂", "👏", "❤️", "🎉"];
 
 fn parse_message(input: &str) -> Result<ChatboxInput, Error> {
     Ok(ChatboxInput::Message(String::from(input)))
@@ -58,6 +91,70 @@ fn parse_command(input: &str) -> Result<ChatboxInput, Error> {
         EXIT_COMMAND => ChatboxCommand::Exit,
         QUIT_COMMAND => ChatboxCommand::Exit,
         BROADCAST_COMMAND => ChatboxCommand::Broadcast,
+        STOP_COMMAND => ChatboxCommand::Stop,
+        SEND_COMMAND => {
+            // paths can have spaces in them
+            let path = input[SEND_COMMAND.len()..].trim();
+            if path.is_empty() {
+                return Err(Error::InvalidUsage {
+                    usage: String::from("/send <path>"),
+                });
+            }
+
+            ChatboxCommand::Send {
+                path: String::from(path),
+            }
+        }
+        ACCEPT_COMMAND => ChatboxCommand::Accept,
+        REJECT_COMMAND => ChatboxCommand::Reject,
+        SHARE_TERMINAL_COMMAND => {
+            // the rest of the line is handed to the shell as is
+            let command = input[SHARE_TERMINAL_COMMAND.len()..].trim();
+            ChatboxCommand::ShareTerminal {
+                command: (!command.is_empty()).then(|| String::from(command)),
+            }
+        }
+        UNSHARE_COMMAND => ChatboxCommand::Unshare,
+        GRANT_COMMAND => ChatboxCommand::Grant {
+            username: tokens.get(1).cloned(),
+        },
+        FOCUS_COMMAND => ChatboxCommand::Focus,
+        REACT_COMMAND => match tokens.get(1) {
+            Some(emoji) if tokens.len() == 2 && emoji.chars().count() <= MAX_REACTION_CHARS => {
+                ChatboxCommand::React {
+                    emoji: emoji.clone(),
+                }
+            }
+            _ => {
+                return Err(Error::InvalidUsage {
+                    usage: String::from("/react <emoji>"),
+                });
+            }
+        },
+        HAND_COMMAND => ChatboxCommand::Hand,
+        BOARD_COMMAND => match tokens.get(1).map(String::as_str) {
+            None => ChatboxCommand::Board,
+            Some("close") => ChatboxCommand::CloseBoard,
+            Some("save") => {
+                let path = input[BOARD_COMMAND.len()..].trim()["save".len()..].trim();
+                if path.is_empty() {
+                    return Err(Error::InvalidUsage {
+                        usage: String::from("/board save <file>"),
+                    });
+                }
+
+                ChatboxCommand::SaveBoard {
+                    path: String::from(path),
+                }
+            }
+            Some(_) => {
+                return Err(Error::InvalidUsage {
+                    usage: String::from("/board [close | save <file>]"),
+                });
+            }
+        },
+        STATS_COMMAND => ChatboxCommand::Stats,
+        DEBUG_COMMAND => ChatboxCommand::Debug,
         _ => return Err(Error::InvalidCommand),
     };
 
@@ -78,6 +175,22 @@ pub trait Parser {
 
 impl Parser for Chatbox {
     fn input(&mut self, key_event: &KeyEvent) -> Result<ChatboxInput, Error> {
+        // AltGr shows up as Control and Alt on Windows, and everything else is typed as usual
+        if key_event.modifiers.contains(KeyModifiers::ALT)
+            && !key_event.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            let command = match key_event.code {
+                KeyCode::Char('h') => Some(ChatboxCommand::Hand),
+                KeyCode::Char(c @ '1'..='5') => Some(ChatboxCommand::React {
+                    emoji: String::from(REACTION_KEYS[c as usize - '1' as usize]),
+                }),
+                _ => None,
+            };
+            if let Some(command) = command {
+                return Ok(ChatboxInput::Command(command));
+            }
+        }
+
         match key_event.code {
             KeyCode::Char(c) => {
                 self.typing_buffer.push(c);
@@ -3,8 +3,12 @@ use std::{collections::VecDeque, io::Write};
 use crossterm::{QueueableCommand, cursor::MoveTo, style::Print};
 
 use crate::{
-    client::message::{Message, RoomMessage, SignalMessage},
+    client::{
+        data_channel::ChatPath,
+        message::{Message, RoomMessage, SignalMessage},
+    },
     layout::Drawable,
+    video::HAND_MARKER,
 };
 
 pub mod command;
@@ -15,7 +19,14 @@ pub struct Chatbox {
     y: u16,
     width: u16,
     height: u16,
+    reserved_rows: u16,
+    // shows signaling messages and the path each chat message took
+    debug: bool,
     content_buffer: VecDeque<String>,
+    // one line per running transfer, kept below the messages
+    progress: Vec<(String, String)>,
+    // everyone with their hand up, including participants that don't have a panel
+    raised_hands: Vec<String>,
     typing_buffer: String,
 }
 
@@ -24,6 +35,8 @@ impl Chatbox {
     const DIVIDER: &str = "─";
     const SPACE: &str = " ";
     const TYPING_INDICATOR: &str = ">";
+    const PROGRESS_FILLED: &str = "█";
+    const PROGRESS_EMPTY: &str = "░";
 
     pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
         let content_buffer = VecDeque::with_capacity(height as usize - 2);
@@ -34,11 +47,24 @@ impl Chatbox {
             y: y,
             width: width,
             height: height,
+            reserved_rows: 0,
+            debug: false,
             content_buffer: content_buffer,
+            progress: Vec::new(),
+            raised_hands: Vec::new(),
             typing_buffer: typing_buffer,
         }
     }
 
+    // keeps the top rows free for another panel, e.g. the stats overlay
+    pub fn set_reserved_rows(&mut self, reserved_rows: u16) {
+        self.reserved_rows = reserved_rows;
+    }
+
+    pub fn toggle_debug(&mut self) {
+        self.debug = !self.debug;
+    }
+
     fn line_width(&self) -> usize {
         self.width as usize - 2 * (Self::PADDING + 1) as usize
     }
@@ -46,27 +72,93 @@ impl Chatbox {
     pub fn receive_message(&mut self, message: &Message) {
         let room_message = match message {
             Message::Room { room_message } => room_message,
-            // TODO: remove debug
-            // _ => return,
-            Message::Signal { signal_message } => {
+            Message::Signal { .. } if !self.debug => return,
+            Message::Signal { signal_message, .. } => {
                 let formatted = match signal_message {
                     SignalMessage::Offer { .. } => String::from("offer"),
                     SignalMessage::Answer { .. } => String::from("answer"),
                     SignalMessage::Candidate { .. } => String::from("candidate"),
+                    SignalMessage::Viewport { .. } => String::from("viewport"),
+                    SignalMessage::Stopped => String::from("stopped"),
+                    SignalMessage::Renegotiate => String::from("renegotiate"),
                 };
                 self.append_to_lines_buffer(&formatted);
                 return;
             }
         };
 
+        self.receive_room_message(room_message, ChatPath::Server);
+    }
+
+    pub fn receive_room_message(&mut self, room_message: &RoomMessage, path: ChatPath) {
         let formatted = match room_message {
-            RoomMessage::Chat { username, content } => format!("[{}]: {}", username, content),
-            RoomMessage::Join { username } => format!("> {} joined", username),
-            RoomMessage::Leave { username } => format!("> {} left", username),
+            RoomMessage::Chat {
+                username, content, ..
+            } if self.debug => format!("[{}] via {}: {}", username, path, content),
+            RoomMessage::Chat {
+                username, content, ..
+            } => format!("[{}]: {}", username, content),
+            RoomMessage::Join { username, .. } => format!("> {} joined", username),
+            RoomMessage::Leave { username, .. } => format!("> {} left", username),
+            RoomMessage::Hand {
+                username, raised, ..
+            } if *raised => format!("> {} raised their hand", username),
+            RoomMessage::Hand { username, .. } => format!("> {} lowered their hand", username),
+            // shown over the sender's panel instead
+            RoomMessage::Reaction { .. } => return,
         };
         self.append_to_lines_buffer(&formatted);
     }
 
+    pub fn set_progress(&mut self, key: &str, description: &str, done: u64, size: u64) {
+        let fraction = match size {
+            0 => 1.0,
+            _ => (done as f64 / size as f64).min(1.0),
+        };
+        let percent = format!(" {:>3.0}%", fraction * 100.0);
+
+        let description = description
+            .chars()
+            .take(self.line_width() / 2)
+            .collect::<String>();
+        let bar_width = self
+            .line_width()
+            .saturating_sub(description.chars().count() + percent.len() + 3);
+        let filled = (fraction * bar_width as f64).round() as usize;
+        let line = format!(
+            "{} [{}{}]{}",
+            description,
+            Self::PROGRESS_FILLED.repeat(filled),
+            Self::PROGRESS_EMPTY.repeat(bar_width - filled),
+            percent
+        );
+
+        match self.progress.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = line,
+            None => self.progress.push((key.to_owned(), line)),
+        }
+    }
+
+    pub fn clear_progress(&mut self, key: &str) {
+        self.progress.retain(|(k, _)| k != key);
+    }
+
+    pub fn set_raised_hands(&mut self, usernames: Vec<String>) {
+        self.raised_hands = usernames;
+    }
+
+    // cut to a single line, since it stays put above the divider
+    fn raised_hands_line(&self) -> Option<String> {
+        if self.raised_hands.is_empty() {
+            return None;
+        }
+
+        let line = format!("{} {}", HAND_MARKER, self.raised_hands.join(", "));
+        textwrap::wrap(&line, self.line_width())
+            .first()
+            .map(|line| line.to_string())
+    }
+
     pub fn log(&mut self, content: &str) {
         let formatted = format!("[info] {}", content);
         self.append_to_lines_buffer(&formatted);
@@ -92,7 +184,7 @@ impl Chatbox {
 
 impl Drawable for Chatbox {
     fn draw(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
-        for i in 1..self.height - 1 {
+        for i in 1 + self.reserved_rows..self.height - 1 {
             stdout
                 .queue(MoveTo(self.x + Self::PADDING + 1, self.y + i as u16))?
                 .queue(Print(Self::SPACE.repeat(self.line_width())))?;
@@ -115,7 +207,36 @@ impl Drawable for Chatbox {
             .queue(MoveTo(self.x + Self::PADDING + 1, divider_y))?
             .queue(Print(Self::DIVIDER.repeat(self.line_width())))?;
 
-        let available = divider_y as usize - 2;
+        let progress_rows = self
+            .progress
+            .len()
+            .min((divider_y as usize - 2).saturating_sub(self.reserved_rows as usize));
+        for (i, (_, line)) in self.progress.iter().take(progress_rows).enumerate() {
+            stdout
+                .queue(MoveTo(
+                    self.x + Self::PADDING + 1,
+                    divider_y - progress_rows as u16 + i as u16,
+                ))?
+                .queue(Print(line))?;
+        }
+
+        let raised_hands = self.raised_hands_line().filter(|_| {
+            (divider_y as usize - 2).saturating_sub(self.reserved_rows as usize) > progress_rows
+        });
+        let raised_hands_rows = usize::from(raised_hands.is_some());
+        if let Some(line) = raised_hands {
+            stdout
+                .queue(MoveTo(
+                    self.x + Self::PADDING + 1,
+                    divider_y - progress_rows as u16 - 1,
+                ))?
+                .queue(Print(line))?;
+        }
+
+        let available = (divider_y as usize - 2)
+            .saturating_sub(self.reserved_rows as usize)
+            .saturating_sub(progress_rows)
+            .saturating_sub(raised_hands_rows);
         let content_buffer_size = self.content_buffer.len();
         let (start_line, lines) = if available < content_buffer_size {
             (content_buffer_size - available, available)
@@ -124,7 +245,10 @@ impl Drawable for Chatbox {
         };
         for i in 0..lines {
             stdout
-                .queue(MoveTo(self.x + Self::PADDING + 1, self.y + i as u16 + 1))?
+                .queue(MoveTo(
+                    self.x + Self::PADDING + 1,
+                    self.y + self.reserved_rows + i as u16 + 1,
+                ))?
                 .queue(Print(&self.content_buffer[start_line + i]))?;
         }
 
@@ -0,0 +1,349 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    Mutex,
+    mpsc::{UnboundedReceiver, unbounded_channel},
+};
+use tracing::info;
+
+use crate::client::{Client, data_channel::ChannelMessage, message::Participant};
+
+// keeps each message well below the 16 KiB data channel limit
+const CELLS_PER_MESSAGE: usize = 128;
+// larger than any terminal the board is drawn in, and small enough to save as text
+const MAX_COLUMNS: u16 = 1024;
+const MAX_ROWS: u16 = 512;
+// more than any number of edits could add up to, so a cell past it is made up, and would
+// otherwise push the clock towards overflowing. Every peer draws the line at the same place,
+// so they all keep or drop the same edits. Once someone pushes the clock up to it, edits
+// there tie and go to the greater author
+const MAX_CLOCK: u64 = 1 << 48;
+
+// One character on the board. Erasing writes a cell without a character, so that the erase
+// can win over older writes the same way a character would
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BoardCell {
+    pub x: u16,
+    pub y: u16,
+    #[serde(default)]
+    pub character: Option<char>,
+    pub clock: u64,
+    pub author: String,
+}
+
+impl BoardCell {
+    // the later write wins, and writes from the same moment are ordered by author
+    fn wins_over(&self, other: &BoardCell) -> bool {
+        (self.clock, &self.author) > (other.clock, &other.author)
+    }
+}
+
+// Sent over the chat channel, both for edits and to catch up a peer whose channel just opened
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum BoardMessage {
+    #[serde(rename = "board_edit")]
+    Edit { cells: Vec<BoardCell> },
+}
+
+#[derive(Debug)]
+pub enum BoardEvent {
+    Edit(Vec<BoardCell>),
+    // the peer should get everything drawn so far
+    Opened(Participant),
+}
+
+// Rows are printed as they are, so a control character could start an escape sequence in our
+// own terminal, and a wide one would push the rest of the row past the border
+fn fits(x: u16, y: u16, character: Option<char>) -> bool {
+    x < MAX_COLUMNS
+        && y < MAX_ROWS
+        && character.is_none_or(|character| {
+            !character.is_control()
+                && textwrap::core::display_width(character.encode_utf8(&mut [0; 4])) == 1
+        })
+}
+
+// A last-writer-wins register per cell, ordered by a Lamport clock, so every peer ends up
+// with the same board whatever order the edits arrive in
+pub struct Canvas {
+    author: String,
+    clock: u64,
+    cells: HashMap<(u16, u16), BoardCell>,
+}
+
+impl Canvas {
+    pub fn new(author: &str) -> Self {
+        Self {
+            author: author.to_owned(),
+            clock: 0,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> Option<char> {
+        self.cells.get(&(x, y)).and_then(|cell| cell.character)
+    }
+
+    // a local edit, which comes after everything seen so far. None if nothing changed
+    pub fn set(&mut self, x: u16, y: u16, character: Option<char>) -> Option<BoardCell> {
+        if !fits(x, y, character) || self.get(x, y) == character {
+            return None;
+        }
+
+        self.clock = (self.clock + 1).min(MAX_CLOCK);
+        let cell = BoardCell {
+            x: x,
+            y: y,
+            character: character,
+            clock: self.clock,
+            author: self.author.clone(),
+        };
+        self.cells.insert((x, y), cell.clone());
+        Some(cell)
+    }
+
+    // returns whether the cell changed what the board shows
+    pub fn merge(&mut self, cell: BoardCell) -> bool {
+        if !fits(cell.x, cell.y, cell.character) || cell.clock > MAX_CLOCK {
+            info!(
+                "ignoring board edit from {} at {},{} with clock {}",
+                cell.author, cell.x, cell.y, cell.clock
+            );
+            return false;
+        }
+        self.clock = self.clock.max(cell.clock);
+
+        match self.cells.get(&(cell.x, cell.y)) {
+            Some(existing) if !cell.wins_over(existing) => false,
+            existing => {
+                let changed = existing.and_then(|existing| existing.character) != cell.character;
+                self.cells.insert((cell.x, cell.y), cell);
+                changed
+            }
+        }
+    }
+
+    pub fn cells(&self) -> Vec<BoardCell> {
+        self.cells.values().cloned().collect()
+    }
+
+    // the smallest rectangle around everything drawn, one line per row
+    pub fn to_text(&self) -> String {
+        let drawn = self
+            .cells
+            .values()
+            .filter(|cell| cell.character.is_some())
+            .collect::<Vec<_>>();
+        let (left, top) = match (
+            drawn.iter().map(|cell| cell.x).min(),
+            drawn.iter().map(|cell| cell.y).min(),
+        ) {
+            (Some(left), Some(top)) => (left, top),
+            _ => return String::new(),
+        };
+        let right = drawn.iter().map(|cell| cell.x).max().unwrap_or(left);
+        let bottom = drawn.iter().map(|cell| cell.y).max().unwrap_or(top);
+
+        let mut text = String::new();
+        for y in top..=bottom {
+            let line = (left..=right)
+                .map(|x| self.get(x, y).unwrap_or(' '))
+                .collect::<String>();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Edits from peers, and peers that need to catch up, are handed to the UI, which owns the board
+pub async fn init_board(client: &Arc<Mutex<Client>>) -> UnboundedReceiver<BoardEvent> {
+    let (sender, receiver) = unbounded_channel();
+    client.lock().await.board_sender = Some(sender);
+    receiver
+}
+
+fn board_messages(cells: Vec<BoardCell>) -> Vec<String> {
+    cells
+        .chunks(CELLS_PER_MESSAGE)
+        .filter_map(|cells| {
+            let message = ChannelMessage::Board(BoardMessage::Edit {
+                cells: cells.to_vec(),
+            });
+            serde_json::to_string(&message).ok()
+        })
+        .collect()
+}
+
+impl Client {
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    // peers without an open channel get these once it opens, along with everything else
+    pub async fn send_board(&self, cells: Vec<BoardCell>) {
+        for json_string in board_messages(cells) {
+            self.send_to_peers(&json_string).await;
+        }
+    }
+
+    pub async fn send_board_to(&self, participant: &Participant, cells: Vec<BoardCell>) {
+        for cells in cells.chunks(CELLS_PER_MESSAGE) {
+            let message = ChannelMessage::Board(BoardMessage::Edit {
+                cells: cells.to_vec(),
+            });
+            if let Err(e) = self.send_to_peer(participant, &message).await {
+                info!("unable to send the board to {}: {}", participant, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(x: u16, y: u16, character: char, clock: u64, author: &str) -> BoardCell {
+        BoardCell {
+            x: x,
+            y: y,
+            character: Some(character),
+            clock: clock,
+            author: author.to_owned(),
+        }
+    }
+
+    fn merged(cells: &[BoardCell]) -> Canvas {
+        let mut canvas = Canvas::new("observer");
+        for cell in cells {
+            canvas.merge(cell.clone());
+        }
+        canvas
+    }
+
+    // edits from three authors, some of them to the same cell
+    fn edits() -> Vec<BoardCell> {
+        let mut alice = Canvas::new("alice");
+        let mut bob = Canvas::new("bob");
+        let mut carol = Canvas::new("carol");
+
+        let mut edits = Vec::new();
+        edits.extend(alice.set(0, 0, Some('a')));
+        edits.extend(bob.set(0, 0, Some('b')));
+        edits.extend(alice.set(1, 0, Some('a')));
+        for edit in edits.clone() {
+            bob.merge(edit.clone());
+            carol.merge(edit);
+        }
+        edits.extend(carol.set(0, 0, Some('c')));
+        edits.extend(bob.set(1, 0, None));
+        edits.extend(bob.set(2, 1, Some('b')));
+        edits.extend(alice.set(2, 1, Some('a')));
+        edits
+    }
+
+    #[test]
+    fn commutative() {
+        let a = cell(3, 4, 'a', 5, "alice");
+        let b = cell(3, 4, 'b', 7, "bob");
+
+        let ab = merged(&[a.clone(), b.clone()]);
+        let ba = merged(&[b, a]);
+        assert_eq!(ab.cells, ba.cells);
+        assert_eq!(ab.get(3, 4), Some('b'));
+    }
+
+    #[test]
+    fn idempotent() {
+        let mut canvas = Canvas::new("observer");
+        let a = cell(1, 2, 'a', 3, "alice");
+
+        assert!(canvas.merge(a.clone()));
+        let once = canvas.cells.clone();
+        assert!(!canvas.merge(a));
+        assert_eq!(canvas.cells, once);
+    }
+
+    #[test]
+    fn converges_in_any_order() {
+        let edits = edits();
+        let expected = merged(&edits);
+
+        let mut reversed = edits.clone();
+        reversed.reverse();
+        let mut rotated = edits.clone();
+        rotated.rotate_left(3);
+        let mut interleaved = edits.iter().step_by(2).cloned().collect::<Vec<_>>();
+        interleaved.extend(edits.iter().skip(1).step_by(2).cloned());
+
+        for order in [reversed, rotated, interleaved] {
+            let canvas = merged(&order);
+            assert_eq!(canvas.cells, expected.cells);
+            assert_eq!(canvas.to_text(), expected.to_text());
+        }
+        assert_eq!(expected.to_text(), "c\n  b\n");
+    }
+
+    #[test]
+    fn equal_clocks_go_to_the_greater_author() {
+        let a = cell(0, 0, 'a', 4, "alice");
+        let b = cell(0, 0, 'b', 4, "bob");
+
+        assert_eq!(merged(&[a.clone(), b.clone()]).get(0, 0), Some('b'));
+        assert_eq!(merged(&[b, a]).get(0, 0), Some('b'));
+    }
+
+    #[test]
+    fn local_edits_win_over_what_was_seen() {
+        let mut canvas = Canvas::new("alice");
+        canvas.merge(cell(0, 0, 'b', 9, "bob"));
+
+        let edit = canvas.set(0, 0, Some('a')).unwrap();
+        assert_eq!(edit.clock, 10);
+        assert_eq!(
+            merged(&[cell(0, 0, 'b', 9, "bob"), edit]).get(0, 0),
+            Some('a')
+        );
+    }
+
+    #[test]
+    fn out_of_range_edits_are_ignored() {
+        let mut canvas = Canvas::new("alice");
+
+        assert!(!canvas.merge(cell(0, 0, 'x', MAX_CLOCK + 1, "mallory")));
+        assert!(!canvas.merge(cell(u16::MAX, u16::MAX, 'x', 1, "mallory")));
+        assert!(canvas.set(MAX_COLUMNS, 0, Some('a')).is_none());
+        assert_eq!(canvas.to_text(), "");
+
+        // the clock didn't move, so editing still works
+        assert_eq!(canvas.set(0, 0, Some('a')).map(|cell| cell.clock), Some(1));
+    }
+
+    #[test]
+    fn clock_stops_at_the_limit() {
+        let mut canvas = Canvas::new("alice");
+        assert!(canvas.merge(cell(0, 0, 'x', MAX_CLOCK, "mallory")));
+
+        let edit = canvas.set(1, 0, Some('a')).unwrap();
+        assert_eq!(edit.clock, MAX_CLOCK);
+        assert!(merged(&[edit]).get(1, 0).is_some());
+    }
+
+    #[test]
+    fn only_single_column_characters() {
+        let mut canvas = Canvas::new("alice");
+
+        for character in ['\x1b', '\x07', '\n', '\u{9b}', '漢', '✋'] {
+            assert!(!canvas.merge(cell(0, 0, character, 1, "mallory")));
+            assert!(canvas.set(0, 0, Some(character)).is_none());
+        }
+        assert_eq!(canvas.to_text(), "");
+
+        assert!(canvas.merge(cell(0, 0, 'é', 1, "bob")));
+        assert!(canvas.set(1, 0, Some('#')).is_some());
+        assert_eq!(canvas.to_text(), "é#\n");
+    }
+}
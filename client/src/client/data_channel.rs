@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Weak},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    Mutex,
+    mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+};
+use tracing::info;
+use webrtc::{
+    data_channel::{
+        RTCDataChannel, data_channel_init::RTCDataChannelInit,
+        data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState,
+    },
+    peer_connection::RTCPeerConnection,
+};
+
+use crate::client::{
+    Client,
+    board::{BoardEvent, BoardMessage},
+    error::Error,
+    message::{Participant, RoomMessage},
+    transfer::{TransferMessage, Transfers},
+};
+
+pub(crate) const CHAT_CHANNEL_LABEL: &str = "chat";
+const RECENT_CHAT_IDS: usize = 256;
+
+// Besides chat messages, the chat channel carries the messages that set up file transfers
+// and edits to the board
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum ChannelMessage {
+    Room(RoomMessage),
+    Transfer(TransferMessage),
+    Board(BoardMessage),
+}
+
+// How a chat message reached us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatPath {
+    Server,
+    DataChannel,
+}
+
+impl std::fmt::Display for ChatPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatPath::Server => write!(f, "server"),
+            ChatPath::DataChannel => write!(f, "p2p"),
+        }
+    }
+}
+
+// Chat messages can arrive over both the data channel and the server, so the ones that were
+// already shown are remembered for a while
+pub(crate) struct RecentIds {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentIds {
+    pub(crate) fn new() -> Self {
+        Self {
+            order: VecDeque::with_capacity(RECENT_CHAT_IDS),
+            ids: HashSet::with_capacity(RECENT_CHAT_IDS),
+        }
+    }
+
+    // false if the id was seen before
+    fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+
+        if self.order.len() == RECENT_CHAT_IDS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+        self.order.push_back(id.to_owned());
+        self.ids.insert(id.to_owned());
+        true
+    }
+}
+
+// Chat messages from data channels are handed to the UI separately from the ones relayed by
+// the server, since they don't go through the web socket
+pub async fn init_chat(client: &Arc<Mutex<Client>>) -> UnboundedReceiver<RoomMessage> {
+    let (sender, receiver) = unbounded_channel();
+    client.lock().await.chat_sender = Some(sender);
+    receiver
+}
+
+// The impolite side opens the channel, so that only one side has to make an offer for it
+pub(crate) async fn open_chat_channel(
+    peer_connection: &RTCPeerConnection,
+) -> Result<Arc<RTCDataChannel>, Error> {
+    let options = RTCDataChannelInit {
+        ordered: Some(true),
+        ..Default::default()
+    };
+    peer_connection
+        .create_data_channel(CHAT_CHANNEL_LABEL, Some(options))
+        .await
+        .map_err(|e| Error::WebRTC { error: e })
+}
+
+pub(crate) fn receive_chat_channel(
+    participant: Participant,
+    data_channel: &Arc<RTCDataChannel>,
+    weak_peer_connection: Weak<RTCPeerConnection>,
+    chat_sender: Option<UnboundedSender<RoomMessage>>,
+    transfers: Option<Arc<Transfers>>,
+    board_sender: Option<UnboundedSender<BoardEvent>>,
+) {
+    let open_participant = participant.clone();
+    let open_transfers = transfers.clone();
+    let open_board_sender = board_sender.clone();
+    let weak_data_channel = Arc::downgrade(data_channel);
+    data_channel.on_open(Box::new(move || {
+        Box::pin(async move {
+            // the peer may have missed edits while there was no channel
+            if let Some(board_sender) = &open_board_sender {
+                let _ = board_sender.send(BoardEvent::Opened(open_participant.clone()));
+            }
+
+            // picks up files that were cut off by the last connection going down
+            let (transfers, data_channel) = match (open_transfers, weak_data_channel.upgrade()) {
+                (Some(transfers), Some(data_channel)) => (transfers, data_channel),
+                _ => return,
+            };
+            for (id, offset) in transfers.interrupted(&open_participant) {
+                let message = ChannelMessage::Transfer(TransferMessage::Accept {
+                    id: id,
+                    offset: offset,
+                });
+                if let Ok(json_string) = serde_json::to_string(&message) {
+                    let _ = data_channel.send_text(json_string).await;
+                }
+            }
+        })
+    }));
+
+    data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let channel_message = match serde_json::from_slice::<ChannelMessage>(&message.data) {
+            Ok(channel_message) => channel_message,
+            Err(e) => {
+                info!("invalid message from {}: {}", participant, e);
+                return Box::pin(async {});
+            }
+        };
+
+        match channel_message {
+            // the channel belongs to the participant, whatever name the message claims
+            ChannelMessage::Room(RoomMessage::Chat { content, id, .. }) => {
+                if let Some(chat_sender) = &chat_sender {
+                    let _ = chat_sender.send(RoomMessage::Chat {
+                        username: participant.username.clone(),
+                        content: content,
+                        id: id,
+                    });
+                }
+            }
+            ChannelMessage::Room(_) => {}
+            ChannelMessage::Transfer(transfer_message) => {
+                if let Some(transfers) = &transfers {
+                    transfers.receive_message(
+                        &participant,
+                        transfer_message,
+                        weak_peer_connection.clone(),
+                    );
+                }
+            }
+            ChannelMessage::Board(BoardMessage::Edit { cells }) => {
+                if let Some(board_sender) = &board_sender {
+                    let _ = board_sender.send(BoardEvent::Edit(cells));
+                }
+            }
+        }
+        Box::pin(async {})
+    }));
+}
+
+impl Client {
+    // Sends to every peer with an open chat channel, returning whether that was everyone
+    pub(crate) async fn send_to_peers(&self, json_string: &str) -> bool {
+        let mut sent_to_everyone = !self.peers.is_empty();
+        for (participant, peer) in &self.peers {
+            let chat_channel = match peer.chat_channel() {
+                Some(chat_channel) if chat_channel.ready_state() == RTCDataChannelState::Open => {
+                    chat_channel
+                }
+                _ => {
+                    sent_to_everyone = false;
+                    continue;
+                }
+            };
+
+            if let Err(e) = chat_channel.send_text(json_string.to_owned()).await {
+                info!("unable to send to {}: {}", participant, e);
+                sent_to_everyone = false;
+            }
+        }
+        sent_to_everyone
+    }
+
+    pub(crate) async fn send_to_peer(
+        &self,
+        participant: &Participant,
+        channel_message: &ChannelMessage,
+    ) -> Result<(), Error> {
+        let chat_channel = match self.peer(participant).and_then(|peer| peer.chat_channel()) {
+            Some(chat_channel) if chat_channel.ready_state() == RTCDataChannelState::Open => {
+                chat_channel
+            }
+            _ => return Err(Error::NoDataChannel),
+        };
+        let json_string = match serde_json::to_string(channel_message) {
+            Ok(json_string) => json_string,
+            Err(_) => return Err(Error::Serialization),
+        };
+
+        chat_channel
+            .send_text(json_string)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::WebRTC { error: e })
+    }
+
+    // our own message, which the server won't echo back if it only went to peers
+    pub(crate) fn echo_chat(&self, room_message: RoomMessage) {
+        if let Some(chat_sender) = &self.chat_sender {
+            let _ = chat_sender.send(room_message);
+        }
+    }
+
+    // false for chat messages that were already received the other way
+    pub fn accept_chat(&mut self, room_message: &RoomMessage) -> bool {
+        match room_message {
+            RoomMessage::Chat { id: Some(id), .. } => self.recent_chat_ids.insert(id),
+            _ => true,
+        }
+    }
+}
@@ -12,6 +12,17 @@ pub enum Error {
     AlreadyInitialized,
     PeerConnectionNotReady,
     WebcamNotReady,
+    NotBroadcasting,
+    NoDataChannel,
+    NoFileOffer,
+    ReadFile { path: String },
+    EmptyFile { path: String },
+    AlreadySharingTerminal,
+    NotSharingTerminal,
+    NoParticipants,
+    UnknownParticipant { username: String },
+    SpawnTerminal { error: String },
+    Viewer,
     WebRTC { error: webrtc::Error },
 }
 
@@ -30,6 +41,17 @@ impl std::fmt::Display for Error {
             Error::AlreadyInitialized => write!(f, "init() has already been called"),
             Error::PeerConnectionNotReady => write!(f, "peer connection is not ready"),
             Error::WebcamNotReady => write!(f, "webcam is not ready"),
+            Error::NotBroadcasting => write!(f, "not broadcasting"),
+            Error::NoDataChannel => write!(f, "no peer connection is ready for data"),
+            Error::NoFileOffer => write!(f, "no file has been offered"),
+            Error::ReadFile { path } => write!(f, "unable to read {}", path),
+            Error::EmptyFile { path } => write!(f, "{} is empty", path),
+            Error::AlreadySharingTerminal => write!(f, "already sharing a terminal"),
+            Error::NotSharingTerminal => write!(f, "not sharing a terminal"),
+            Error::NoParticipants => write!(f, "nobody else is in the room"),
+            Error::UnknownParticipant { username } => write!(f, "{} isn't in the room", username),
+            Error::SpawnTerminal { error } => write!(f, "unable to start terminal: {}", error),
+            Error::Viewer => write!(f, "viewers only watch"),
             Error::WebRTC { error } => write!(f, "{}", error),
         }
     }
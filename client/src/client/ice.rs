@@ -0,0 +1,125 @@
+use std::env;
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use webrtc::{
+    ice_transport::ice_server::RTCIceServer,
+    peer_connection::{
+        configuration::RTCConfiguration, policy::ice_transport_policy::RTCIceTransportPolicy,
+    },
+};
+
+const DEFAULT_STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+
+lazy_static! {
+    static ref ICE_SERVERS: Option<Vec<String>> = env::var("ICE_SERVERS").ok().map(|urls| {
+        urls.split(',')
+            .map(|url| url.trim().to_owned())
+            .filter(|url| !url.is_empty())
+            .collect()
+    });
+    static ref ICE_USERNAME: String = env::var("ICE_USERNAME").unwrap_or_default();
+    static ref ICE_CREDENTIAL: String = env::var("ICE_CREDENTIAL").unwrap_or_default();
+    static ref ICE_TRANSPORT_POLICY: RTCIceTransportPolicy = env::var("ICE_TRANSPORT_POLICY")
+        .map(|policy| RTCIceTransportPolicy::from(policy.as_str()))
+        .unwrap_or_default();
+    static ref ICE_MODE: String = env::var("ICE_MODE").unwrap_or_default();
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub credential: String,
+}
+
+impl From<IceServer> for RTCIceServer {
+    fn from(server: IceServer) -> Self {
+        RTCIceServer {
+            urls: server.urls,
+            username: server.username,
+            credential: server.credential,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IceConfig {
+    servers: Vec<RTCIceServer>,
+    transport_policy: RTCIceTransportPolicy,
+    // servers were set locally, so the ones handed out by the signaling server are ignored
+    pinned: bool,
+}
+
+impl IceConfig {
+    // ICE_MODE=lan gathers host candidates only, which needs no outside server at all.
+    // Otherwise ICE_SERVERS takes a comma separated list of stun: and turn: urls, and
+    // ICE_USERNAME and ICE_CREDENTIAL are used for the turn: ones
+    pub fn from_env() -> Self {
+        if ICE_MODE.eq_ignore_ascii_case("lan") {
+            return Self {
+                servers: Vec::new(),
+                transport_policy: RTCIceTransportPolicy::All,
+                pinned: true,
+            };
+        }
+
+        let (servers, pinned) = match &*ICE_SERVERS {
+            Some(urls) => {
+                let (turn_urls, stun_urls): (Vec<String>, Vec<String>) = urls
+                    .iter()
+                    .cloned()
+                    .partition(|url| url.starts_with("turn:") || url.starts_with("turns:"));
+
+                let mut servers = Vec::new();
+                if !stun_urls.is_empty() {
+                    servers.push(RTCIceServer {
+                        urls: stun_urls,
+                        ..Default::default()
+                    });
+                }
+                if !turn_urls.is_empty() {
+                    servers.push(RTCIceServer {
+                        urls: turn_urls,
+                        username: ICE_USERNAME.clone(),
+                        credential: ICE_CREDENTIAL.clone(),
+                    });
+                }
+                (servers, true)
+            }
+            None => (
+                vec![RTCIceServer {
+                    urls: vec![DEFAULT_STUN_SERVER.to_owned()],
+                    ..Default::default()
+                }],
+                false,
+            ),
+        };
+
+        Self {
+            servers: servers,
+            transport_policy: *ICE_TRANSPORT_POLICY,
+            pinned: pinned,
+        }
+    }
+
+    // Servers from the signaling server replace the default, e.g. to add TURN with
+    // short-lived credentials
+    pub fn use_servers(&mut self, servers: Vec<IceServer>) {
+        if self.pinned || servers.is_empty() {
+            return;
+        }
+
+        self.servers = servers.into_iter().map(RTCIceServer::from).collect();
+    }
+
+    pub fn configuration(&self) -> RTCConfiguration {
+        RTCConfiguration {
+            ice_servers: self.servers.clone(),
+            ice_transport_policy: self.transport_policy,
+            ..Default::default()
+        }
+    }
+}
@@ -15,6 +15,11 @@ pub enum Message {
         room_message: RoomMessage,
     },
     Signal {
+        // sender and recipient in a mesh call, where no recipient means everyone
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<Participant>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<Participant>,
         #[serde(flatten)]
         signal_message: SignalMessage,
     },
@@ -24,13 +29,96 @@ pub enum Message {
 #[serde(tag = "type")]
 pub enum RoomMessage {
     #[serde(rename = "chat")]
-    Chat { username: String, content: String },
+    Chat {
+        username: String,
+        content: String,
+        // tells apart copies of the same message that came over the server and a data channel
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
 
     #[serde(rename = "join")]
-    Join { username: String },
+    Join {
+        username: String,
+        #[serde(default)]
+        session: String,
+    },
 
     #[serde(rename = "leave")]
-    Leave { username: String },
+    Leave {
+        username: String,
+        #[serde(default)]
+        session: String,
+    },
+
+    // an emoji shown over the sender's panel for a moment
+    #[serde(rename = "reaction")]
+    Reaction {
+        username: String,
+        #[serde(default)]
+        session: String,
+        content: String,
+    },
+
+    #[serde(rename = "hand")]
+    Hand {
+        username: String,
+        #[serde(default)]
+        session: String,
+        raised: bool,
+    },
+}
+
+// A client in a room. The session tells apart clients that share a username, e.g. two
+// terminals on the same machine, and is empty for messages from before sessions were added.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(from = "ParticipantRepr")]
+pub struct Participant {
+    pub username: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub session: String,
+}
+
+impl Participant {
+    // an empty session on either side matches any client with the username
+    pub fn matches(&self, other: &Participant) -> bool {
+        self.username == other.username
+            && (self.session.is_empty()
+                || other.session.is_empty()
+                || self.session == other.session)
+    }
+}
+
+impl std::fmt::Display for Participant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParticipantRepr {
+    Username(String),
+    Participant {
+        username: String,
+        #[serde(default)]
+        session: String,
+    },
+}
+
+impl From<ParticipantRepr> for Participant {
+    fn from(participant: ParticipantRepr) -> Self {
+        match participant {
+            ParticipantRepr::Username(username) => Self {
+                username: username,
+                session: String::new(),
+            },
+            ParticipantRepr::Participant { username, session } => Self {
+                username: username,
+                session: session,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -44,6 +132,31 @@ pub enum SignalMessage {
 
     #[serde(rename = "candidate")]
     Candidate { payload: RTCIceCandidateInit },
+
+    #[serde(rename = "viewport")]
+    Viewport { payload: Viewport },
+
+    // the sender's video track is gone, rather than just frozen
+    #[serde(rename = "stopped")]
+    Stopped,
+
+    // asks the impolite side to make an offer, so that offers never collide once connected
+    #[serde(rename = "renegotiate")]
+    Renegotiate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Renderer {
+    #[serde(rename = "half-block")]
+    HalfBlock,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Viewport {
+    pub username: String,
+    pub width: usize,
+    pub height: usize,
+    pub renderer: Renderer,
 }
 
 pub(crate) fn convert_stream_message(message: &tungstenite::Message) -> Result<Message, Error> {
@@ -54,3 +167,83 @@ pub(crate) fn convert_stream_message(message: &tungstenite::Message) -> Result<M
 
     serde_json::from_str::<Message>(&data).map_err(|_| Error::Deserialization)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal_from(json: &str) -> Option<Participant> {
+        match serde_json::from_str::<Message>(json) {
+            Ok(Message::Signal { from, .. }) => from,
+            other => panic!("not a signal: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn participant_from_username() {
+        let participant = serde_json::from_str::<Participant>(r#""alice""#).unwrap();
+        assert_eq!(participant.username, "alice");
+        assert_eq!(participant.session, "");
+    }
+
+    #[test]
+    fn participant_from_object() {
+        let participant =
+            serde_json::from_str::<Participant>(r#"{"username":"alice","session":"1a"}"#).unwrap();
+        assert_eq!(participant.username, "alice");
+        assert_eq!(participant.session, "1a");
+
+        let participant = serde_json::from_str::<Participant>(r#"{"username":"alice"}"#).unwrap();
+        assert_eq!(participant.session, "");
+    }
+
+    #[test]
+    fn participant_round_trip() {
+        let participant = Participant {
+            username: String::from("alice"),
+            session: String::from("1a"),
+        };
+        let json = serde_json::to_string(&participant).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Participant>(&json).unwrap(),
+            participant
+        );
+
+        // an empty session is left out, like older clients would send it
+        let participant = Participant {
+            username: String::from("alice"),
+            session: String::new(),
+        };
+        assert_eq!(
+            serde_json::to_string(&participant).unwrap(),
+            r#"{"username":"alice"}"#
+        );
+    }
+
+    #[test]
+    fn signal_participants() {
+        let candidate = r#""payload":{"candidate":"candidate:1 1 udp 1 10.0.0.1 5000 typ host"}"#;
+
+        let from = signal_from(&format!(
+            r#"{{"type":"candidate","from":{{"username":"alice","session":"1a"}},{}}}"#,
+            candidate
+        ));
+        assert_eq!(
+            from,
+            Some(Participant {
+                username: String::from("alice"),
+                session: String::from("1a"),
+            })
+        );
+
+        let from = signal_from(&format!(
+            r#"{{"type":"candidate","from":"alice",{}}}"#,
+            candidate
+        ));
+        assert_eq!(from.map(|from| from.session), Some(String::new()));
+
+        // clients from before the mesh don't send one at all
+        let from = signal_from(&format!(r#"{{"type":"candidate",{}}}"#, candidate));
+        assert_eq!(from, None);
+    }
+}
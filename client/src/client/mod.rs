@@ -1,8 +1,20 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use futures::{SinkExt, StreamExt};
 use tokio::{
-    sync::mpsc::{Receiver, UnboundedReceiver},
+    sync::{
+        Notify,
+        mpsc::{Receiver, UnboundedSender},
+        watch,
+    },
+    task::JoinHandle,
     time::timeout,
 };
 use tracing::info;
@@ -10,33 +22,153 @@ use webrtc::{
     api::media_engine::MIME_TYPE_H264,
     ice_transport::ice_candidate::RTCIceCandidateInit,
     media::Sample,
-    peer_connection::{RTCPeerConnection, sdp::session_description::RTCSessionDescription},
-    rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
+    peer_connection::{
+        RTCPeerConnection, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription,
+    },
+    rtcp::{
+        payload_feedbacks::{
+            full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+        },
+        receiver_report::ReceiverReport,
+    },
+    rtp_transceiver::{rtp_codec::RTCRtpCodecCapability, rtp_sender::RTCRtpSender},
     track::track_local::{TrackLocal, track_local_static_sample::TrackLocalStaticSample},
 };
 
 use crate::{
     chat::command::{ChatboxCommand, ChatboxInput},
     client::{
+        board::BoardEvent,
+        data_channel::RecentIds,
         error::Error,
-        message::{Message, SignalMessage},
+        ice::IceConfig,
+        message::{Message, Participant, Renderer, RoomMessage, SignalMessage, Viewport},
         room::{MessageReceiver, WriteStream},
+        signaling::{Peer, PeerTrack},
+        terminal::{SharedTerminal, TerminalEvent},
+        transfer::Transfers,
+    },
+    stats::FrameCounter,
+    video::{
+        bitrate::{BandwidthEstimator, BitrateMeter, EncoderTarget},
+        channel::FrameReceiver,
+        jitter::ReceiveStats,
+        latency::{FrameTiming, round_trip_time},
+        pool::SharedFrame,
+        webcam::{ViewportLimit, Webcam},
     },
-    video::webcam::Webcam,
 };
 
+pub mod board;
+pub mod data_channel;
 pub mod error;
+pub mod ice;
 pub mod message;
 pub mod room;
 pub mod signaling;
+pub mod terminal;
+pub mod transfer;
+
+// Tells apart this run of the client from others with the same username
+fn new_session() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    format!("{:x}", nanos ^ ((std::process::id() as u64) << 32))
+}
 
 pub struct Client {
     username: String,
+    session: String,
     webcam: Option<Webcam>,
+    viewport: Option<Viewport>,
+    peer_viewports: HashMap<Participant, Viewport>,
+    viewport_limit: Option<Arc<watch::Sender<ViewportLimit>>>,
     http_client: reqwest::Client,
+    ice_config: IceConfig,
     write_stream: Option<WriteStream>,
     message_receiver: Option<Receiver<Result<Message, Error>>>,
-    peer_connection: Option<Arc<RTCPeerConnection>>,
+    weak_self: Weak<tokio::sync::Mutex<Client>>,
+    peers: HashMap<Participant, Peer>,
+    peer_track_sender: Option<UnboundedSender<PeerTrack>>,
+    chat_sender: Option<UnboundedSender<RoomMessage>>,
+    sent_chats: u64,
+    recent_chat_ids: RecentIds,
+    transfers: Option<Arc<Transfers>>,
+    sent_files: u64,
+    terminal_sender: Option<UnboundedSender<TerminalEvent>>,
+    shared_terminal: Option<Arc<SharedTerminal>>,
+    board_sender: Option<UnboundedSender<BoardEvent>>,
+    hand_raised: bool,
+    // only receives, without ever opening the webcam
+    viewer: bool,
+    broadcast: Option<Broadcast>,
+    send_bitrate: Option<Arc<AtomicU32>>,
+    skipped_frames: Option<Arc<AtomicU64>>,
+    dropped_encoded_frames: Option<Arc<AtomicU64>>,
+    sent_frames: Arc<FrameCounter>,
+}
+
+// The local video track, which is shared by the peer connections to every participant
+struct Broadcast {
+    video_track: Arc<TrackLocalStaticSample>,
+    stop: Arc<Notify>,
+    // hands the webcam back once stopped, so that broadcasting can start again
+    video_task: Option<JoinHandle<Webcam>>,
+    bandwidth_estimator: Arc<Mutex<BandwidthEstimator>>,
+    encoder_target: Arc<watch::Sender<EncoderTarget>>,
+    force_keyframe: Arc<AtomicBool>,
+    round_trip_micros: Arc<AtomicU32>,
+}
+
+impl Broadcast {
+    async fn add_to(
+        &self,
+        peer_connection: &RTCPeerConnection,
+    ) -> Result<Arc<RTCRtpSender>, Error> {
+        let rtp_sender = peer_connection
+            .add_track(Arc::clone(&self.video_track) as Arc<dyn TrackLocal + Send + Sync>)
+            .await
+            .map_err(|e| Error::WebRTC { error: e })?;
+
+        // there's a single encoder, so the estimate is shared and the worst link wins
+        let bandwidth_estimator = self.bandwidth_estimator.clone();
+        let encoder_target = self.encoder_target.clone();
+        let force_keyframe = self.force_keyframe.clone();
+        let round_trip_micros = self.round_trip_micros.clone();
+        let rtcp_sender = rtp_sender.clone();
+        tokio::spawn(async move {
+            let mut rtcp_buf = vec![0u8; 1500];
+            while let Ok((packets, _)) = rtcp_sender.read(&mut rtcp_buf).await {
+                let mut bandwidth_estimator = match bandwidth_estimator.lock() {
+                    Ok(bandwidth_estimator) => bandwidth_estimator,
+                    Err(_) => return,
+                };
+                for packet in &packets {
+                    bandwidth_estimator.on_rtcp(packet.as_ref());
+
+                    let packet = packet.as_any();
+                    if let Some(rtt) = packet
+                        .downcast_ref::<ReceiverReport>()
+                        .and_then(|report| round_trip_time(report, SystemTime::now()))
+                    {
+                        round_trip_micros.store(rtt.as_micros() as u32, Ordering::Relaxed);
+                    }
+                    if packet.is::<PictureLossIndication>() || packet.is::<FullIntraRequest>() {
+                        force_keyframe.store(true, Ordering::Release);
+                    }
+                }
+
+                if let Some(target) = bandwidth_estimator.poll_target() {
+                    encoder_target.send_replace(target);
+                }
+            }
+        });
+
+        Ok(rtp_sender)
+    }
 }
 
 impl Client {
@@ -47,11 +179,33 @@ impl Client {
 
         Self {
             username: username,
+            session: new_session(),
             webcam: None,
+            viewport: None,
+            peer_viewports: HashMap::new(),
+            viewport_limit: None,
             http_client: reqwest::Client::new(),
+            ice_config: IceConfig::from_env(),
             write_stream: None,
             message_receiver: None,
-            peer_connection: None,
+            weak_self: Weak::new(),
+            peers: HashMap::new(),
+            peer_track_sender: None,
+            chat_sender: None,
+            sent_chats: 0,
+            recent_chat_ids: RecentIds::new(),
+            transfers: None,
+            sent_files: 0,
+            terminal_sender: None,
+            shared_terminal: None,
+            board_sender: None,
+            hand_raised: false,
+            viewer: false,
+            broadcast: None,
+            send_bitrate: None,
+            skipped_frames: None,
+            dropped_encoded_frames: None,
+            sent_frames: Arc::new(FrameCounter::default()),
         }
     }
 
@@ -88,8 +242,33 @@ impl Client {
                     Ok(None)
                 }
                 ChatboxCommand::Broadcast => {
+                    // adding the track to each peer connection triggers renegotiation
                     self.start_broadcast().await?;
-                    self.send_offer().await?;
+                    Ok(None)
+                }
+                ChatboxCommand::Stop => {
+                    self.stop_broadcast().await?;
+                    Ok(None)
+                }
+                ChatboxCommand::Send { path } => self.offer_file(path).await.map(Some),
+                ChatboxCommand::Accept => {
+                    self.accept_file().await?;
+                    Ok(None)
+                }
+                ChatboxCommand::Reject => self.reject_file().await,
+                ChatboxCommand::ShareTerminal { command } => {
+                    self.share_terminal(command.as_deref()).await.map(Some)
+                }
+                ChatboxCommand::Unshare => self.unshare_terminal().map(Some),
+                ChatboxCommand::Grant { username } => {
+                    self.grant_terminal(username.as_deref()).await.map(Some)
+                }
+                ChatboxCommand::React { emoji } => {
+                    self.send_reaction(emoji).await?;
+                    Ok(None)
+                }
+                ChatboxCommand::Hand => {
+                    self.toggle_hand().await?;
                     Ok(None)
                 }
                 _ => Ok(None),
@@ -100,12 +279,39 @@ impl Client {
 
     pub async fn receive_message(&mut self, message: &Message) -> Result<(), Error> {
         match message {
-            Message::Room { .. } => Ok(()),
-            Message::Signal { signal_message } => match signal_message {
-                SignalMessage::Offer { payload } => self.handle_offer(&payload).await,
-                SignalMessage::Answer { payload } => self.handle_answer(&payload).await,
-                SignalMessage::Candidate { payload } => self.handle_candidate(&payload).await,
+            Message::Room { room_message } => match room_message {
+                RoomMessage::Join { username, session } => {
+                    self.send_viewport().await?;
+                    if self.hand_raised {
+                        self.send_hand().await?;
+                    }
+                    self.connect_to_peer(&Participant {
+                        username: username.clone(),
+                        session: session.clone(),
+                    })
+                    .await
+                }
+                RoomMessage::Leave { username, session } => {
+                    let participant = Participant {
+                        username: username.clone(),
+                        session: session.clone(),
+                    };
+                    self.peer_viewports
+                        .retain(|key, _| !key.matches(&participant));
+                    self.update_viewport_limit();
+                    self.remove_peer(&participant).await;
+                    Ok(())
+                }
+                _ => Ok(()),
             },
+            Message::Signal {
+                from,
+                to,
+                signal_message,
+            } => {
+                self.receive_signal(from.as_ref(), to.as_ref(), signal_message)
+                    .await
+            }
         }
     }
 
@@ -116,37 +322,138 @@ impl Client {
         }
     }
 
-    pub async fn start_webcam(&mut self) -> UnboundedReceiver<Vec<u8>> {
+    pub fn set_viewer(&mut self, viewer: bool) {
+        self.viewer = viewer;
+    }
+
+    pub async fn start_webcam(&mut self) -> FrameReceiver<SharedFrame> {
         let mut webcam = Webcam::new();
         let local_video_receiver = webcam.start_webcam();
+        self.viewport_limit = Some(webcam.viewport_limit());
+        self.skipped_frames = Some(webcam.skipped_frames());
+        self.dropped_encoded_frames = Some(webcam.dropped_frames());
         self.webcam = Some(webcam);
 
         local_video_receiver
     }
 
+    pub fn set_viewport(&mut self, width: usize, height: usize, renderer: Renderer) {
+        self.viewport = Some(Viewport {
+            username: self.username.clone(),
+            width: width,
+            height: height,
+            renderer: renderer,
+        });
+    }
+
+    fn handle_viewport(&mut self, from: &Participant, viewport: &Viewport) {
+        self.peer_viewports.insert(from.clone(), viewport.clone());
+        self.update_viewport_limit();
+    }
+
+    fn update_viewport_limit(&self) {
+        let viewport_limit = match &self.viewport_limit {
+            Some(viewport_limit) => viewport_limit,
+            None => return,
+        };
+
+        // encode at the largest size that any receiver will display
+        let limit = self
+            .peer_viewports
+            .values()
+            .map(|viewport| (viewport.width, viewport.height))
+            .reduce(|(w0, h0), (w1, h1)| (w0.max(w1), h0.max(h1)));
+        viewport_limit.send_replace(limit);
+    }
+
+    pub fn send_bitrate(&self) -> Option<u32> {
+        self.send_bitrate
+            .as_ref()
+            .map(|send_bitrate| send_bitrate.load(Ordering::Relaxed))
+    }
+
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+            .as_ref()
+            .map_or(0, |skipped| skipped.load(Ordering::Relaxed))
+    }
+
+    pub fn dropped_encoded_frames(&self) -> u64 {
+        self.dropped_encoded_frames
+            .as_ref()
+            .map_or(0, |dropped| dropped.load(Ordering::Relaxed))
+    }
+
+    pub fn sent_frames(&self) -> Arc<FrameCounter> {
+        self.sent_frames.clone()
+    }
+
+    pub fn peer_connections(&self) -> Vec<Arc<RTCPeerConnection>> {
+        self.peers
+            .values()
+            .map(|peer| peer.peer_connection.clone())
+            .collect()
+    }
+
+    pub fn receive_stats(&self, participant: &Participant) -> ReceiveStats {
+        self.peer(participant)
+            .map_or(ReceiveStats::default(), |peer| peer.receive_stats())
+    }
+
+    pub fn connection_state(&self, participant: &Participant) -> Option<RTCPeerConnectionState> {
+        self.peer(participant)
+            .map(|peer| peer.peer_connection.connection_state())
+    }
+
+    pub fn request_keyframe(&self, participant: &Participant) {
+        if let Some(peer) = self.peer(participant) {
+            peer.request_keyframe();
+        }
+    }
+
+    // re-announces our viewport, e.g. after the video panels have been reflowed
+    pub async fn update_viewport(
+        &mut self,
+        width: usize,
+        height: usize,
+        renderer: Renderer,
+    ) -> Result<(), Error> {
+        self.set_viewport(width, height, renderer);
+        self.send_viewport().await
+    }
+
     async fn start_broadcast(&mut self) -> Result<(), Error> {
-        let peer_connection = match &self.peer_connection {
-            Some(peer_connection) => peer_connection,
-            None => return Err(Error::PeerConnectionNotReady),
+        if self.viewer {
+            return Err(Error::Viewer);
+        }
+        let webcam = match &self.webcam {
+            Some(webcam) => webcam,
+            None => return Err(Error::WebcamNotReady),
         };
         let video_track = Arc::new(TrackLocalStaticSample::new(
             RTCRtpCodecCapability {
                 mime_type: String::from(MIME_TYPE_H264),
                 ..Default::default()
             },
-            String::from("video"),
-            String::from("webrtc-rs"),
+            // lets peers tell whose video a track is
+            self.session.clone(),
+            self.username.clone(),
         ));
 
-        let rtp_sender = peer_connection
-            .add_track(Arc::clone(&video_track) as Arc<dyn TrackLocal + Send + Sync>)
-            .await
-            .map_err(|e| Error::WebRTC { error: e })?;
-
-        tokio::spawn(async move {
-            let mut rtcp_buf = vec![0u8; 1500];
-            while let Ok((_, _)) = rtp_sender.read(&mut rtcp_buf).await {}
-        });
+        let round_trip_micros = Arc::new(AtomicU32::new(0));
+        let stop = Arc::new(Notify::new());
+        let mut broadcast = Broadcast {
+            video_track: video_track.clone(),
+            stop: stop.clone(),
+            video_task: None,
+            bandwidth_estimator: Arc::new(Mutex::new(BandwidthEstimator::new())),
+            encoder_target: webcam.encoder_target(),
+            force_keyframe: webcam.force_keyframe(),
+            round_trip_micros: round_trip_micros.clone(),
+        };
+        for peer in self.peers.values_mut() {
+            peer.video_sender = Some(broadcast.add_to(&peer.peer_connection).await?);
+        }
 
         let mut webcam = match self.webcam.take() {
             Some(webcam) => webcam,
@@ -154,20 +461,49 @@ impl Client {
         };
         webcam.start_broadcast();
 
-        tokio::spawn(async move {
+        let send_bitrate = Arc::new(AtomicU32::new(0));
+        self.send_bitrate = Some(send_bitrate.clone());
+        let sent_frames = self.sent_frames.clone();
+
+        let video_task = tokio::spawn(async move {
             info!("started video thread");
 
+            let mut bitrate_meter = BitrateMeter::new();
             loop {
-                match webcam.next().await {
-                    Some(data) => {
+                let encoded_frame = tokio::select! {
+                    encoded_frame = webcam.next() => encoded_frame,
+                    _ = stop.notified() => break,
+                };
+
+                match encoded_frame {
+                    Some(encoded_frame) => {
+                        if let Some(bitrate) = bitrate_meter.record(encoded_frame.data.len()) {
+                            send_bitrate.store(bitrate, Ordering::Relaxed);
+                        }
+                        sent_frames.record(encoded_frame.encode);
+
+                        // lets the receiver work out where the latency comes from
+                        let timing = FrameTiming {
+                            capture_time: encoded_frame.timestamp,
+                            capture: encoded_frame.capture,
+                            encode: encoded_frame.encode,
+                            round_trip_time: Duration::from_micros(
+                                round_trip_micros.load(Ordering::Relaxed) as u64,
+                            ),
+                        };
                         let sample = Sample {
-                            data: data,
+                            data: encoded_frame.data,
+                            timestamp: encoded_frame.timestamp,
+                            duration: encoded_frame.duration,
                             ..Default::default()
                         };
 
-                        if let Err(e) = video_track.write_sample(&sample).await {
+                        if let Err(e) = video_track
+                            .write_sample_with_extensions(&sample, &timing.header_extensions())
+                            .await
+                        {
                             info!("unable to write to video track: {}", e);
-                            return;
+                            break;
                         }
                     }
                     None => {
@@ -176,10 +512,53 @@ impl Client {
                     }
                 }
             }
+
+            webcam
         });
+        broadcast.video_task = Some(video_task);
+        self.broadcast = Some(broadcast);
 
         Ok(())
     }
+
+    async fn stop_broadcast(&mut self) -> Result<(), Error> {
+        let mut broadcast = match self.broadcast.take() {
+            Some(broadcast) => broadcast,
+            None => return Err(Error::NotBroadcasting),
+        };
+
+        // removing the track from each peer connection triggers renegotiation. One that fails
+        // mustn't keep the encoder running, so the first error is only returned at the end
+        let mut result = Ok(());
+        for (participant, peer) in self.peers.iter_mut() {
+            if let Some(video_sender) = peer.video_sender.take()
+                && let Err(e) = peer.peer_connection.remove_track(&video_sender).await
+            {
+                info!(
+                    "unable to remove the video track for {}: {}",
+                    participant, e
+                );
+                if result.is_ok() {
+                    result = Err(Error::WebRTC { error: e });
+                }
+            }
+        }
+
+        broadcast.stop.notify_one();
+        if let Some(video_task) = broadcast.video_task.take() {
+            match video_task.await {
+                Ok(mut webcam) => {
+                    webcam.stop_broadcast();
+                    self.webcam = Some(webcam);
+                }
+                Err(e) => info!("video thread failed: {}", e),
+            }
+        }
+        self.send_bitrate = None;
+
+        let stopped = self.send_signal(None, SignalMessage::Stopped).await;
+        result.and(stopped)
+    }
 }
 
 pub(crate) trait RoomHandler {
@@ -190,8 +569,22 @@ pub(crate) trait RoomHandler {
 }
 
 pub(crate) trait SignalHandler {
-    async fn send_offer(&mut self) -> Result<(), Error>;
-    async fn handle_offer(&mut self, offer: &RTCSessionDescription) -> Result<(), Error>;
-    async fn handle_answer(&mut self, answer: &RTCSessionDescription) -> Result<(), Error>;
-    async fn handle_candidate(&mut self, candidate: &RTCIceCandidateInit) -> Result<(), Error>;
+    async fn send_offer(&mut self, participant: &Participant) -> Result<(), Error>;
+    async fn handle_offer(
+        &mut self,
+        participant: &Participant,
+        offer: &RTCSessionDescription,
+    ) -> Result<(), Error>;
+    async fn handle_answer(
+        &mut self,
+        participant: &Participant,
+        answer: &RTCSessionDescription,
+    ) -> Result<(), Error>;
+    async fn handle_candidate(
+        &mut self,
+        participant: &Participant,
+        candidate: &RTCIceCandidateInit,
+    ) -> Result<(), Error>;
+    async fn handle_renegotiate(&mut self, participant: &Participant) -> Result<(), Error>;
+    async fn send_viewport(&mut self) -> Result<(), Error>;
 }
@@ -12,10 +12,12 @@ use tokio_tungstenite::{
     MaybeTlsStream, WebSocketStream, connect_async,
     tungstenite::{self, client::IntoClientRequest},
 };
+use tracing::info;
 
 use crate::client::{
     Client, RoomHandler,
     error::Error,
+    ice::IceServer,
     message::{Message, RoomMessage, convert_stream_message},
 };
 
@@ -27,6 +29,7 @@ lazy_static! {
     static ref CREATE_ROOM_URL: String = format!("http://{}/create", *HOST);
     static ref JOIN_ROOM_URL: String = format!("http://{}/join", *HOST);
     static ref CONNECT_TO_ROOM_URL: String = format!("ws://{}/ws", *HOST);
+    static ref ICE_SERVERS_URL: String = format!("http://{}/ice", *HOST);
 }
 
 #[derive(Deserialize)]
@@ -66,13 +69,35 @@ async fn join_room(
     Ok(response)
 }
 
+#[derive(Deserialize)]
+struct IceServersResponse {
+    #[serde(rename = "iceServers")]
+    pub(crate) ice_servers: Vec<IceServer>,
+}
+
+async fn fetch_ice_servers(
+    client: &reqwest::Client,
+    token: &str,
+) -> Result<IceServersResponse, Box<dyn std::error::Error>> {
+    let response = client
+        .get(&*ICE_SERVERS_URL)
+        .query(&[("token", token)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<IceServersResponse>()
+        .await?;
+    Ok(response)
+}
+
 async fn connect_to_room(
     token: &str,
     username: &str,
+    session: &str,
 ) -> Result<(WriteStream, MessageReceiver), Box<dyn std::error::Error>> {
     let url = format!(
-        "{}?token={}&username={}",
-        *CONNECT_TO_ROOM_URL, token, username
+        "{}?token={}&username={}&session={}",
+        *CONNECT_TO_ROOM_URL, token, username, session
     )
     .into_client_request()?;
 
@@ -93,6 +118,54 @@ async fn connect_to_room(
     Ok((write_stream, rx))
 }
 
+impl Client {
+    // Older servers don't hand out ICE servers, in which case the defaults are kept
+    async fn fetch_ice_servers(&mut self, token: &str) {
+        match timeout(Self::TIMEOUT, fetch_ice_servers(&self.http_client, token)).await {
+            Ok(Ok(response)) => self.ice_config.use_servers(response.ice_servers),
+            Ok(Err(e)) => info!("unable to fetch ice servers: {e}"),
+            Err(_) => info!("timed out fetching ice servers"),
+        }
+    }
+
+    // the server echoes these back, which is when they show up on our own panel
+    async fn send_room_message(&mut self, message: &RoomMessage) -> Result<(), Error> {
+        let json = match serde_json::to_string(message) {
+            Ok(json) => json,
+            Err(_) => return Err(Error::Serialization),
+        };
+        self.send_message(json).await
+    }
+
+    pub(crate) async fn send_reaction(&mut self, emoji: &str) -> Result<(), Error> {
+        let message = RoomMessage::Reaction {
+            username: self.username.clone(),
+            session: self.session.clone(),
+            content: emoji.to_owned(),
+        };
+        self.send_room_message(&message).await
+    }
+
+    pub(crate) async fn toggle_hand(&mut self) -> Result<(), Error> {
+        self.hand_raised = !self.hand_raised;
+        if let Err(e) = self.send_hand().await {
+            self.hand_raised = !self.hand_raised;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // also sent when someone joins, so that they know the hand is up
+    pub(crate) async fn send_hand(&mut self) -> Result<(), Error> {
+        let message = RoomMessage::Hand {
+            username: self.username.clone(),
+            session: self.session.clone(),
+            raised: self.hand_raised,
+        };
+        self.send_room_message(&message).await
+    }
+}
+
 impl RoomHandler for Client {
     async fn create_and_connect_to_room(&mut self) -> Result<String, Error> {
         let response = match timeout(Self::TIMEOUT, create_room(&self.http_client)).await {
@@ -105,6 +178,7 @@ impl RoomHandler for Client {
             Err(_) => return Err(Error::CreateRoom),
         };
 
+        self.fetch_ice_servers(&token).await;
         let (write_stream, rx) = self.connect_to_room(&token).await?;
         self.write_stream = Some(write_stream);
         self.message_receiver = Some(rx);
@@ -127,6 +201,7 @@ impl RoomHandler for Client {
             }
         };
 
+        self.fetch_ice_servers(&token).await;
         let (write_stream, rx) = self.connect_to_room(&token).await?;
         self.write_stream = Some(write_stream);
         self.message_receiver = Some(rx);
@@ -135,7 +210,12 @@ impl RoomHandler for Client {
     }
 
     async fn connect_to_room(&self, token: &str) -> Result<(WriteStream, MessageReceiver), Error> {
-        let response = match timeout(Self::TIMEOUT, connect_to_room(token, &self.username)).await {
+        let response = match timeout(
+            Self::TIMEOUT,
+            connect_to_room(token, &self.username, &self.session),
+        )
+        .await
+        {
             Ok(response) => response,
             Err(_) => return Err(Error::Timeout),
         };
@@ -144,15 +224,23 @@ impl RoomHandler for Client {
     }
 
     async fn send_chat(&mut self, content: &str) -> Result<(), Error> {
+        self.sent_chats += 1;
         let message = RoomMessage::Chat {
             username: self.username.clone(),
             content: String::from(content),
+            id: Some(format!("{}-{}", self.session, self.sent_chats)),
         };
         let json = match serde_json::to_string(&message) {
             Ok(json) => json,
             Err(_) => return Err(Error::Serialization),
         };
 
+        // peers without an open data channel get it through the server, which also echoes it
+        // back to us
+        if self.send_to_peers(&json).await {
+            self.echo_chat(message);
+            return Ok(());
+        }
         self.send_message(json).await
     }
 }
@@ -1,45 +1,159 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use tokio::sync::{
-    Mutex,
-    mpsc::{UnboundedReceiver, unbounded_channel},
+use tokio::{
+    sync::{
+        Mutex, Notify,
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    },
+    time::timeout,
 };
 use tracing::info;
 use webrtc::{
     api::{
         APIBuilder, interceptor_registry::register_default_interceptors, media_engine::MediaEngine,
     },
-    ice_transport::{
-        ice_candidate::{RTCIceCandidate, RTCIceCandidateInit},
-        ice_server::RTCIceServer,
-    },
-    interceptor::registry::Registry,
+    data_channel::RTCDataChannel,
+    ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit},
+    interceptor::{registry::Registry, twcc::sender::Sender},
     peer_connection::{
-        RTCPeerConnection, configuration::RTCConfiguration,
-        sdp::session_description::RTCSessionDescription,
+        RTCPeerConnection,
+        offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+        signaling_state::RTCSignalingState,
     },
-    track::track_local::{TrackLocalWriter, track_local_static_rtp::TrackLocalStaticRTP},
+    rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication,
+    rtp::packet::Packet,
+    rtp_transceiver::{
+        RTCRtpTransceiverInit,
+        rtp_codec::{RTCRtpHeaderExtensionCapability, RTPCodecType},
+        rtp_receiver::RTCRtpReceiver,
+        rtp_sender::RTCRtpSender,
+        rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    },
+    track::track_remote::TrackRemote,
 };
 
 use crate::{
-    client::{Client, SignalHandler, error::Error, message::SignalMessage},
-    video::encoding::convert_payload_to_nal_units,
+    client::{
+        Client, SignalHandler,
+        data_channel::{CHAT_CHANNEL_LABEL, open_chat_channel, receive_chat_channel},
+        error::Error,
+        ice::IceConfig,
+        message::{Message, Participant, SignalMessage},
+        terminal::{TERMINAL_CHANNEL_LABEL, receive_terminal_channel},
+        transfer::FILE_CHANNEL_PREFIX,
+    },
+    video::{
+        channel::{FrameReceiver, frame_channel},
+        encoding::H264Depacketizer,
+        handler::PeerFrame,
+        jitter::{JitterBuffer, Playout, ReceiveStats},
+        latency::{
+            ABS_CAPTURE_TIME_URI, ClockOffsetEstimator, FRAME_TIMING_URI, FrameTiming,
+            LatencyBreakdown,
+        },
+    },
 };
 
-async fn create_peer_connction() -> Result<RTCPeerConnection, webrtc::Error> {
-    let config = RTCConfiguration {
-        ice_servers: vec![RTCIceServer {
-            urls: vec!["stun:stun.l.google.com:19302".to_owned()],
-            ..Default::default()
-        }],
-        ..Default::default()
-    };
+const RECEIVE_STATS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+const PEER_FRAME_CAPACITY: usize = 2;
+const DISCONNECTED_GRACE_PERIOD: Duration = Duration::from_secs(3);
+const ICE_RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+// stands in for a client from before the mesh that was in the room before us
+const UNKNOWN_USERNAME: &str = "unknown";
+
+struct KeyframeRequester {
+    peer_connection: Weak<RTCPeerConnection>,
+    media_ssrc: u32,
+    last_request: Option<Instant>,
+}
+
+impl KeyframeRequester {
+    // don't flood a bad link with requests while the keyframe is on its way
+    const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+    fn new(peer_connection: Weak<RTCPeerConnection>, media_ssrc: u32) -> Self {
+        Self {
+            peer_connection: peer_connection,
+            media_ssrc: media_ssrc,
+            last_request: None,
+        }
+    }
+
+    async fn request(&mut self) {
+        if self
+            .last_request
+            .is_some_and(|last_request| last_request.elapsed() < Self::MIN_INTERVAL)
+        {
+            return;
+        }
+
+        let peer_connection = match self.peer_connection.upgrade() {
+            Some(peer_connection) => peer_connection,
+            None => return,
+        };
+        self.last_request = Some(Instant::now());
+
+        let picture_loss_indication = PictureLossIndication {
+            sender_ssrc: 0,
+            media_ssrc: self.media_ssrc,
+        };
+        if let Err(e) = peer_connection
+            .write_rtcp(&[Box::new(picture_loss_indication)])
+            .await
+        {
+            info!("unable to send picture loss indication: {e}");
+        }
+    }
+}
+
+fn depacketize_frame(depacketizer: &mut H264Depacketizer, packets: &[Packet]) -> Vec<u8> {
+    let mut frame_buffer = Vec::new();
+    for rtp in packets {
+        match depacketizer.depacketize(rtp.header.sequence_number, &rtp.payload) {
+            Ok(nal_units) => nal_units
+                .iter()
+                .for_each(|nal_unit| frame_buffer.extend_from_slice(nal_unit)),
+            Err(e) => {
+                info!("dropping frame: {e}");
+                return Vec::new();
+            }
+        }
+    }
+    frame_buffer
+}
+
+async fn create_peer_connction(ice_config: &IceConfig) -> Result<RTCPeerConnection, webrtc::Error> {
+    let config = ice_config.configuration();
 
     let mut engine = MediaEngine::default();
     engine.register_default_codecs()?;
 
-    let mut registry = Registry::new();
-    registry = register_default_interceptors(registry, &mut engine)?;
+    // capture timestamps for measuring glass-to-glass latency
+    for uri in [ABS_CAPTURE_TIME_URI, FRAME_TIMING_URI] {
+        engine.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: uri.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )?;
+    }
+
+    // the defaults only generate transport-wide congestion control feedback, so the sender is
+    // added on top for peers to send feedback for the bandwidth estimator. This is what
+    // configure_twcc does, without registering a second feedback generator
+    let mut registry = register_default_interceptors(Registry::new(), &mut engine)?;
+    registry.add(Box::new(Sender::builder()));
 
     let api = APIBuilder::new()
         .with_media_engine(engine)
@@ -49,104 +163,550 @@ async fn create_peer_connction() -> Result<RTCPeerConnection, webrtc::Error> {
     api.new_peer_connection(config).await
 }
 
-pub async fn init_peer_connection(
-    client: &Arc<Mutex<Client>>,
-) -> Result<UnboundedReceiver<Vec<u8>>, Error> {
-    let peer_connection = match create_peer_connction().await {
-        Ok(peer_connection) => peer_connection,
-        Err(e) => return Err(Error::WebRTC { error: e }),
+fn is_down(weak_peer_connection: &Weak<RTCPeerConnection>) -> bool {
+    weak_peer_connection
+        .upgrade()
+        .is_some_and(|peer_connection| {
+            matches!(
+                peer_connection.connection_state(),
+                RTCPeerConnectionState::Disconnected | RTCPeerConnectionState::Failed
+            )
+        })
+}
+
+// Tries an ICE restart first, which keeps the media session, and builds a new peer connection if
+// that doesn't help either. Only the impolite side restarts, so that both restarts don't collide
+async fn recover_connection(
+    weak_client: Weak<Mutex<Client>>,
+    participant: Participant,
+    weak_peer_connection: Weak<RTCPeerConnection>,
+    recovering: Arc<AtomicBool>,
+) {
+    // disconnected often sorts itself out
+    tokio::time::sleep(DISCONNECTED_GRACE_PERIOD).await;
+
+    let mut polite = true;
+    if is_down(&weak_peer_connection)
+        && let Some(client) = weak_client.upgrade()
+    {
+        let mut client = client.lock().await;
+        if let Some(peer) = client.current_peer(&participant, &weak_peer_connection) {
+            polite = peer.polite;
+            if !polite && let Err(e) = client.restart_ice(&participant).await {
+                info!("unable to restart ice with {}: {}", participant, e);
+            }
+        }
+    }
+
+    // the polite side gives the other one a chance to rebuild first
+    let timeout = match polite {
+        true => 2 * ICE_RESTART_TIMEOUT,
+        false => ICE_RESTART_TIMEOUT,
     };
+    tokio::time::sleep(timeout).await;
+
+    if is_down(&weak_peer_connection)
+        && let Some(client) = weak_client.upgrade()
+    {
+        let mut client = client.lock().await;
+        if client
+            .current_peer(&participant, &weak_peer_connection)
+            .is_some()
+            && let Err(e) = client.rebuild_peer(&participant).await
+        {
+            info!("unable to reconnect to {}: {}", participant, e);
+        }
+    }
+
+    recovering.store(false, Ordering::SeqCst);
+}
+
+// A new certificate means that the peer started over with a new peer connection
+fn fingerprint(sdp: &str) -> Option<&str> {
+    sdp.lines()
+        .find_map(|line| line.trim().strip_prefix("a=fingerprint:"))
+}
+
+// A remote video track and whose it is
+pub struct PeerTrack {
+    pub participant: Participant,
+    pub frames: FrameReceiver<PeerFrame>,
+}
+
+// One per remote participant in the mesh
+pub(crate) struct Peer {
+    pub(crate) peer_connection: Arc<RTCPeerConnection>,
+    receive_stats: Arc<std::sync::Mutex<ReceiveStats>>,
+    keyframe_request: Arc<Notify>,
+    pub(crate) video_sender: Option<Arc<RTCRtpSender>>,
+    // https://w3c.github.io/webrtc-pc/#perfect-negotiation-example
+    polite: bool,
+    ignore_offer: bool,
+    // an offer and answer went through, after which only the impolite side makes offers, since
+    // an offer of ours that collides can't be rolled back anymore
+    negotiated: bool,
+    // negotiation was needed while another one was going on
+    offer_pending: bool,
+    // candidates that arrived before the description they belong to
+    pending_candidates: Vec<RTCIceCandidateInit>,
+    // opened by one side and announced to the other, so it shows up later there
+    chat_channel: Arc<std::sync::Mutex<Option<Arc<RTCDataChannel>>>>,
+    // opened by the peer while it shares a terminal
+    terminal_channel: Arc<std::sync::Mutex<Option<Arc<RTCDataChannel>>>>,
+}
+
+impl Peer {
+    pub(crate) fn receive_stats(&self) -> ReceiveStats {
+        match self.receive_stats.lock() {
+            Ok(receive_stats) => *receive_stats,
+            Err(_) => ReceiveStats::default(),
+        }
+    }
+
+    pub(crate) fn request_keyframe(&self) {
+        self.keyframe_request.notify_one();
+    }
+
+    pub(crate) fn chat_channel(&self) -> Option<Arc<RTCDataChannel>> {
+        match self.chat_channel.lock() {
+            Ok(chat_channel) => chat_channel.clone(),
+            Err(_) => None,
+        }
+    }
+
+    pub(crate) fn terminal_channel(&self) -> Option<Arc<RTCDataChannel>> {
+        match self.terminal_channel.lock() {
+            Ok(terminal_channel) => terminal_channel.clone(),
+            Err(_) => None,
+        }
+    }
+}
 
+// Peer connections are created as participants show up, so they need a way back into the
+// client to send their candidates, and a way to hand their tracks over to the UI
+pub async fn init_signaling(client: &Arc<Mutex<Client>>) -> UnboundedReceiver<PeerTrack> {
     let (sender, receiver) = unbounded_channel();
-    peer_connection.on_track(Box::new(move |track, _, _| {
-        let sender = sender.clone();
-        Box::pin(async move {
-            info!(
-                "received remote track: {} (codec {})",
-                track.kind(),
-                track.codec().capability.mime_type
-            );
 
-            let local_track = Arc::new(TrackLocalStaticRTP::new(
-                track.codec().capability.clone(),
-                String::from("video"),
-                String::from("webrtc-rs"),
-            ));
-
-            tokio::spawn(async move {
-                let mut nal_buffer = Vec::new();
-                let mut frame_buffer: Vec<u8> = Vec::new();
-
-                while let Ok((rtp, _)) = track.read_rtp().await {
-                    let _ = local_track.write_rtp(&rtp).await;
-
-                    match convert_payload_to_nal_units(&rtp.payload, &mut nal_buffer) {
-                        Some(nal_units) => nal_units
-                            .iter()
-                            .for_each(|nal_unit| frame_buffer.extend_from_slice(&nal_unit)),
-                        None => {
-                            continue;
+    let mut client_mut = client.lock().await;
+    client_mut.weak_self = Arc::downgrade(client);
+    client_mut.peer_track_sender = Some(sender);
+
+    receiver
+}
+
+// Tracks are labelled with the sender's username (the stream id) and session (the track id), but
+// older clients use fixed ids, and a peer can only send its own video, so the label is only
+// used when it names whoever the connection is with
+fn track_participant(labelled: Participant, participant: &Participant) -> Participant {
+    if !labelled.session.is_empty() && labelled.matches(participant) {
+        return labelled;
+    }
+
+    info!(
+        "track from {} (session {:?}) is labelled as {} (session {:?})",
+        participant.username, participant.session, labelled.username, labelled.session
+    );
+    participant.clone()
+}
+
+fn receive_track(
+    participant: Participant,
+    track: Arc<TrackRemote>,
+    receiver: Arc<RTCRtpReceiver>,
+    weak_peer_connection: Weak<RTCPeerConnection>,
+    peer_track_sender: Option<UnboundedSender<PeerTrack>>,
+    receive_stats: Arc<std::sync::Mutex<ReceiveStats>>,
+    keyframe_request: Arc<Notify>,
+) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    let mut keyframe_requester = KeyframeRequester::new(weak_peer_connection, track.ssrc());
+    Box::pin(async move {
+        info!(
+            "received remote track from {}: {} {} (codec {})",
+            participant,
+            track.kind(),
+            track.id(),
+            track.codec().capability.mime_type
+        );
+
+        let (sender, frames) = frame_channel(PEER_FRAME_CAPACITY);
+        let peer_track = PeerTrack {
+            participant: track_participant(
+                Participant {
+                    username: track.stream_id(),
+                    session: track.id(),
+                },
+                &participant,
+            ),
+            frames: frames,
+        };
+        match peer_track_sender.map(|peer_track_sender| peer_track_sender.send(peer_track)) {
+            Some(Ok(())) => {}
+            _ => return,
+        }
+
+        let header_extensions = receiver.get_parameters().await.header_extensions;
+        let extension_id = |uri: &str| {
+            header_extensions
+                .iter()
+                .find(|extension| extension.uri == uri)
+                .map(|extension| extension.id as u8)
+        };
+        let timing_extension_ids =
+            extension_id(ABS_CAPTURE_TIME_URI).zip(extension_id(FRAME_TIMING_URI));
+
+        tokio::spawn(async move {
+            let mut jitter_buffer = JitterBuffer::new(track.codec().capability.clock_rate);
+            let mut last_stats_log = Instant::now();
+            let mut depacketizer = H264Depacketizer::new();
+            let mut clock_offset = ClockOffsetEstimator::new();
+
+            loop {
+                // wake up periodically so that gaps are given up on even if no packets arrive
+                let rtp = tokio::select! {
+                    result = timeout(JitterBuffer::MAX_DELAY, track.read_rtp()) => match result {
+                        Ok(Ok((rtp, _))) => Some(rtp),
+                        Ok(Err(_)) => break,
+                        Err(_) => None,
+                    },
+                    _ = keyframe_request.notified() => {
+                        keyframe_requester.request().await;
+                        continue;
+                    },
+                };
+
+                let now = Instant::now();
+                if let Some(rtp) = rtp {
+                    jitter_buffer.push(rtp, now);
+                }
+
+                while let Some(playout) = jitter_buffer.pop(now) {
+                    let (frame_buffer, latency) = match playout {
+                        Playout::Frame(packets) => {
+                            let latency = timing_extension_ids
+                                .and_then(|(capture_time_id, frame_timing_id)| {
+                                    FrameTiming::from_packet(
+                                        packets.last()?,
+                                        capture_time_id,
+                                        frame_timing_id,
+                                    )
+                                })
+                                .map(|timing| LatencyBreakdown {
+                                    capture: timing.capture,
+                                    encode: timing.encode,
+                                    network: clock_offset.network_delay(&timing, SystemTime::now()),
+                                    ..Default::default()
+                                });
+                            (depacketize_frame(&mut depacketizer, &packets), latency)
                         }
+                        Playout::Dropped => (Vec::new(), None),
+                    };
+
+                    if frame_buffer.is_empty() {
+                        depacketizer.reset();
+                        keyframe_requester.request().await;
                     }
 
-                    if rtp.header.marker {
-                        match sender.send(frame_buffer) {
-                            Ok(_) => frame_buffer = Vec::new(),
-                            Err(e) => {
-                                info!("failed to send frame: {e}");
-                                return;
-                            }
-                        }
+                    // an empty frame lets the decoder know that it needs to wait for a keyframe
+                    let frame = PeerFrame {
+                        data: frame_buffer,
+                        latency: latency,
+                    };
+                    if let Err(e) = sender.send(frame) {
+                        info!("failed to send frame: {e}");
+                        return;
                     }
                 }
-            });
-        })
-    }));
 
-    let weak_client = Arc::downgrade(&client);
-    peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
-        let inner_weak_client = weak_client.clone();
-        Box::pin(async move {
-            let candidate = match candidate {
-                Some(candidate) => candidate,
-                None => return,
-            };
+                let stats = jitter_buffer.stats();
+                if let Ok(mut receive_stats) = receive_stats.lock() {
+                    *receive_stats = stats;
+                }
+                if last_stats_log.elapsed() > RECEIVE_STATS_LOG_INTERVAL {
+                    info!("receive stats: {:?}", stats);
+                    last_stats_log = Instant::now();
+                }
+            }
+        });
+    })
+}
 
-            let payload = match candidate.to_json() {
-                Ok(payload) => payload,
-                Err(_) => return,
-            };
+impl Client {
+    pub(crate) fn participant(&self) -> Participant {
+        Participant {
+            username: self.username.clone(),
+            session: self.session.clone(),
+        }
+    }
 
-            let message = SignalMessage::Candidate { payload: payload };
-            let json_string = match serde_json::to_string(&message) {
-                Ok(json) => json,
-                Err(_) => return,
-            };
+    // the key of the peer state for a participant, which may have been created before we
+    // knew their session
+    fn peer_key(&self, participant: &Participant) -> Option<Participant> {
+        if self.peers.contains_key(participant) {
+            return Some(participant.clone());
+        }
+
+        self.peers
+            .keys()
+            .find(|key| key.matches(participant))
+            .cloned()
+    }
+
+    pub(crate) fn peer(&self, participant: &Participant) -> Option<&Peer> {
+        self.peer_key(participant)
+            .and_then(|key| self.peers.get(&key))
+    }
+
+    fn peer_mut(&mut self, participant: &Participant) -> Option<&mut Peer> {
+        self.peer_key(participant)
+            .and_then(|key| self.peers.get_mut(&key))
+    }
+
+    async fn add_peer(
+        &mut self,
+        participant: &Participant,
+    ) -> Result<Arc<RTCPeerConnection>, Error> {
+        let peer_connection = match create_peer_connction(&self.ice_config).await {
+            Ok(peer_connection) => Arc::new(peer_connection),
+            Err(e) => return Err(Error::WebRTC { error: e }),
+        };
+
+        let receive_stats = Arc::new(std::sync::Mutex::new(ReceiveStats::default()));
+        let keyframe_request = Arc::new(Notify::new());
+
+        let track_participant = participant.clone();
+        let track_receive_stats = receive_stats.clone();
+        let track_keyframe_request = keyframe_request.clone();
+        let peer_track_sender = self.peer_track_sender.clone();
+        let weak_peer_connection = Arc::downgrade(&peer_connection);
+        peer_connection.on_track(Box::new(move |track, receiver, _| {
+            receive_track(
+                track_participant.clone(),
+                track,
+                receiver,
+                weak_peer_connection.clone(),
+                peer_track_sender.clone(),
+                track_receive_stats.clone(),
+                track_keyframe_request.clone(),
+            )
+        }));
+
+        let weak_client = self.weak_self.clone();
+        let candidate_participant = participant.clone();
+        peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let inner_weak_client = weak_client.clone();
+            let participant = candidate_participant.clone();
+            Box::pin(async move {
+                // no candidate means gathering is done, which peers are told about with an
+                // empty one
+                let payload = match candidate {
+                    Some(candidate) => match candidate.to_json() {
+                        Ok(payload) => payload,
+                        Err(_) => return,
+                    },
+                    None => RTCIceCandidateInit::default(),
+                };
+
+                let inner_self_ref = match inner_weak_client.upgrade() {
+                    Some(inner_self_ref) => inner_self_ref,
+                    None => return,
+                };
+
+                let mut client = inner_self_ref.lock().await;
+                let _ = client
+                    .send_signal(
+                        Some(&participant),
+                        SignalMessage::Candidate { payload: payload },
+                    )
+                    .await;
+            })
+        }));
+
+        let gathering_participant = participant.clone();
+        peer_connection.on_ice_gathering_state_change(Box::new(move |state| {
+            info!("ice gathering for {}: {}", gathering_participant, state);
+            Box::pin(async {})
+        }));
+
+        let weak_client = self.weak_self.clone();
+        let state_participant = participant.clone();
+        let state_recovering = Arc::new(AtomicBool::new(false));
+        let weak_peer_connection = Arc::downgrade(&peer_connection);
+        peer_connection.on_peer_connection_state_change(Box::new(move |state| {
+            info!("connection to {}: {}", state_participant, state);
+
+            let is_down = matches!(
+                state,
+                RTCPeerConnectionState::Disconnected | RTCPeerConnectionState::Failed
+            );
+            if is_down && !state_recovering.swap(true, Ordering::SeqCst) {
+                tokio::spawn(recover_connection(
+                    weak_client.clone(),
+                    state_participant.clone(),
+                    weak_peer_connection.clone(),
+                    state_recovering.clone(),
+                ));
+            }
+            Box::pin(async {})
+        }));
+
+        // adding or removing tracks makes an offer from whichever side made the change
+        let weak_client = self.weak_self.clone();
+        let negotiation_participant = participant.clone();
+        peer_connection.on_negotiation_needed(Box::new(move || {
+            let weak_client = weak_client.clone();
+            let participant = negotiation_participant.clone();
+            Box::pin(async move {
+                // this runs on the peer connection's operation queue, which making the offer
+                // may have to wait on
+                tokio::spawn(async move {
+                    let client = match weak_client.upgrade() {
+                        Some(client) => client,
+                        None => return,
+                    };
+
+                    let mut client = client.lock().await;
+                    if let Err(e) = client.send_offer(&participant).await {
+                        info!("unable to renegotiate with {}: {}", participant, e);
+                    }
+                });
+            })
+        }));
+
+        let chat_channel = Arc::new(std::sync::Mutex::new(None));
+        let data_channel_participant = participant.clone();
+        let data_channel_chat_channel = chat_channel.clone();
+        let chat_sender = self.chat_sender.clone();
+        let transfers = self.transfers.clone();
+        let terminal_channel = Arc::new(std::sync::Mutex::new(None));
+        let data_channel_terminal_channel = terminal_channel.clone();
+        let terminal_sender = self.terminal_sender.clone();
+        let board_sender = self.board_sender.clone();
+        let weak_peer_connection = Arc::downgrade(&peer_connection);
+        peer_connection.on_data_channel(Box::new(move |data_channel| {
+            let label = data_channel.label();
+            if label == CHAT_CHANNEL_LABEL {
+                receive_chat_channel(
+                    data_channel_participant.clone(),
+                    &data_channel,
+                    weak_peer_connection.clone(),
+                    chat_sender.clone(),
+                    transfers.clone(),
+                    board_sender.clone(),
+                );
+                if let Ok(mut chat_channel) = data_channel_chat_channel.lock() {
+                    *chat_channel = Some(data_channel);
+                }
+            } else if label == TERMINAL_CHANNEL_LABEL {
+                receive_terminal_channel(
+                    data_channel_participant.clone(),
+                    &data_channel,
+                    terminal_sender.clone(),
+                );
+                if let Ok(mut terminal_channel) = data_channel_terminal_channel.lock() {
+                    *terminal_channel = Some(data_channel);
+                }
+            } else if label.starts_with(FILE_CHANNEL_PREFIX)
+                && let Some(transfers) = &transfers
+            {
+                transfers.receive_file(&data_channel_participant, data_channel);
+            }
+            Box::pin(async {})
+        }));
 
-            let inner_self_ref = match inner_weak_client.upgrade() {
-                Some(inner_self_ref) => inner_self_ref,
-                None => return,
+        // viewers never add a track, so they ask for video up front instead
+        if self.viewer {
+            let init = RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                send_encodings: Vec::new(),
             };
+            if let Err(e) = peer_connection
+                .add_transceiver_from_kind(RTPCodecType::Video, Some(init))
+                .await
+            {
+                return Err(Error::WebRTC { error: e });
+            }
+        }
 
-            let mut client = inner_self_ref.lock().await;
-            let _ = client.send_message(json_string).await;
-        })
-    }));
+        // both sides need to agree on who backs down when offers collide
+        let polite = self.participant() < *participant;
+        if !polite {
+            let data_channel = open_chat_channel(&peer_connection).await?;
+            receive_chat_channel(
+                participant.clone(),
+                &data_channel,
+                Arc::downgrade(&peer_connection),
+                self.chat_sender.clone(),
+                self.transfers.clone(),
+                self.board_sender.clone(),
+            );
+            if let Ok(mut chat_channel) = chat_channel.lock() {
+                *chat_channel = Some(data_channel);
+            }
+        }
 
-    client.lock().await.peer_connection = Some(Arc::new(peer_connection));
+        self.peers.insert(
+            participant.clone(),
+            Peer {
+                peer_connection: peer_connection.clone(),
+                receive_stats: receive_stats,
+                keyframe_request: keyframe_request,
+                video_sender: None,
+                polite: polite,
+                ignore_offer: false,
+                negotiated: false,
+                offer_pending: false,
+                pending_candidates: Vec::new(),
+                chat_channel: chat_channel,
+                terminal_channel: terminal_channel,
+            },
+        );
 
-    Ok(receiver)
-}
+        if let Some(broadcast) = &self.broadcast {
+            let video_sender = broadcast.add_to(&peer_connection).await?;
+            if let Some(peer) = self.peers.get_mut(participant) {
+                peer.video_sender = Some(video_sender);
+            }
+        }
 
-impl SignalHandler for Client {
-    async fn send_offer(&mut self) -> Result<(), Error> {
-        let peer_connection = match &self.peer_connection {
-            Some(peer_connection) => peer_connection,
+        if let Err(e) = self
+            .share_terminal_with(participant, &peer_connection)
+            .await
+        {
+            info!("unable to share the terminal with {}: {}", participant, e);
+        }
+
+        Ok(peer_connection)
+    }
+
+    // the peer, as long as it still uses the given peer connection
+    fn current_peer(
+        &self,
+        participant: &Participant,
+        weak_peer_connection: &Weak<RTCPeerConnection>,
+    ) -> Option<&Peer> {
+        self.peer(participant).filter(|peer| {
+            weak_peer_connection
+                .upgrade()
+                .is_some_and(|peer_connection| Arc::ptr_eq(&peer.peer_connection, &peer_connection))
+        })
+    }
+
+    async fn restart_ice(&mut self, participant: &Participant) -> Result<(), Error> {
+        let peer_connection = match self.peer(participant) {
+            Some(peer) => peer.peer_connection.clone(),
             None => return Err(Error::PeerConnectionNotReady),
         };
+        if peer_connection.signaling_state() != RTCSignalingState::Stable {
+            return Ok(());
+        }
 
+        info!("restarting ice with {}", participant);
+        let options = RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        };
         let offer = peer_connection
-            .create_offer(None)
+            .create_offer(Some(options))
             .await
             .map_err(|e| Error::WebRTC { error: e })?;
         peer_connection
@@ -154,8 +714,153 @@ impl SignalHandler for Client {
             .await
             .map_err(|e| Error::WebRTC { error: e })?;
 
-        let message = SignalMessage::Offer {
-            payload: offer.clone(),
+        self.send_signal(Some(participant), SignalMessage::Offer { payload: offer })
+            .await
+    }
+
+    // starts over with a new peer connection, which the peer notices from the new certificate
+    // in our offer. Boxed, since the new peer connection can end up here again
+    fn rebuild_peer<'a>(
+        &'a mut self,
+        participant: &'a Participant,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Box::pin(async move {
+            info!("reconnecting to {}", participant);
+            self.remove_peer(participant).await;
+            self.add_peer(participant).await?;
+            self.send_offer(participant).await
+        })
+    }
+
+    async fn peer_connection_for(
+        &mut self,
+        participant: &Participant,
+    ) -> Result<Arc<RTCPeerConnection>, Error> {
+        match self.peer(participant) {
+            Some(peer) => Ok(peer.peer_connection.clone()),
+            None => self.add_peer(participant).await,
+        }
+    }
+
+    // Clients from before the mesh don't say who their signals are from, but they only ever
+    // had one connection, so the signals go to the one peer that joined without a session. If
+    // there's none, the client was already in the room, and gets a peer of its own
+    fn legacy_sender(&self) -> Option<Participant> {
+        let legacy = self
+            .peers
+            .keys()
+            .filter(|key| key.session.is_empty())
+            .collect::<Vec<_>>();
+        match legacy[..] {
+            [] => Some(Participant {
+                username: String::from(UNKNOWN_USERNAME),
+                session: String::new(),
+            }),
+            [participant] => Some(participant.clone()),
+            _ => None,
+        }
+    }
+
+    // sets up a connection to a participant that we haven't heard from before
+    pub(crate) async fn connect_to_peer(&mut self, participant: &Participant) -> Result<(), Error> {
+        if participant.matches(&self.participant()) || self.peer(participant).is_some() {
+            return Ok(());
+        }
+
+        self.add_peer(participant).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn remove_peer(&mut self, participant: &Participant) {
+        let key = match self.peer_key(participant) {
+            Some(key) => key,
+            None => return,
+        };
+
+        if let Some(peer) = self.peers.remove(&key)
+            && let Err(e) = peer.peer_connection.close().await
+        {
+            info!("unable to close peer connection to {}: {}", participant, e);
+        }
+    }
+
+    async fn add_pending_candidates(&mut self, participant: &Participant) {
+        let peer = match self.peer_mut(participant) {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        for candidate in std::mem::take(&mut peer.pending_candidates) {
+            if let Err(e) = peer.peer_connection.add_ice_candidate(candidate).await {
+                info!("unable to add candidate from {}: {}", participant, e);
+            }
+        }
+    }
+
+    // back in stable, so an offer that had to wait can be made now
+    async fn finish_negotiation(&mut self, participant: &Participant) -> Result<(), Error> {
+        let offer_pending = match self.peer_mut(participant) {
+            Some(peer) => {
+                peer.negotiated = true;
+                std::mem::take(&mut peer.offer_pending)
+            }
+            None => false,
+        };
+        if offer_pending {
+            self.send_offer(participant).await
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) async fn receive_signal(
+        &mut self,
+        from: Option<&Participant>,
+        to: Option<&Participant>,
+        signal_message: &SignalMessage,
+    ) -> Result<(), Error> {
+        let from = match from.cloned().or_else(|| self.legacy_sender()) {
+            Some(from) => from,
+            None => {
+                info!("ignoring signal without a sender, since several peers could have sent it");
+                return Ok(());
+            }
+        };
+        let from = &from;
+        if *from == self.participant() {
+            return Ok(());
+        }
+        if to.is_some_and(|to| !to.matches(&self.participant())) {
+            info!(
+                "ignoring signal from {} that is addressed to someone else",
+                from
+            );
+            return Ok(());
+        }
+
+        match signal_message {
+            SignalMessage::Offer { payload } => self.handle_offer(from, payload).await,
+            SignalMessage::Answer { payload } => self.handle_answer(from, payload).await,
+            SignalMessage::Candidate { payload } => self.handle_candidate(from, payload).await,
+            SignalMessage::Viewport { payload } => {
+                self.handle_viewport(from, payload);
+                self.connect_to_peer(from).await
+            }
+            // the panel is updated by the UI
+            SignalMessage::Stopped => Ok(()),
+            SignalMessage::Renegotiate => self.handle_renegotiate(from).await,
+        }
+    }
+
+    pub(crate) async fn send_signal(
+        &mut self,
+        to: Option<&Participant>,
+        signal_message: SignalMessage,
+    ) -> Result<(), Error> {
+        let message = Message::Signal {
+            from: Some(self.participant()),
+            to: to.cloned(),
+            signal_message: signal_message,
         };
         let json_string = match serde_json::to_string(&message) {
             Ok(json) => json,
@@ -164,17 +869,102 @@ impl SignalHandler for Client {
 
         self.send_message(json_string).await
     }
+}
 
-    async fn handle_offer(&mut self, offer: &RTCSessionDescription) -> Result<(), Error> {
-        let peer_connection = match &self.peer_connection {
-            Some(peer_connection) => peer_connection,
+impl SignalHandler for Client {
+    async fn send_offer(&mut self, participant: &Participant) -> Result<(), Error> {
+        let peer = match self.peer_mut(participant) {
+            Some(peer) => peer,
+            None => return Err(Error::PeerConnectionNotReady),
+        };
+        let peer_connection = peer.peer_connection.clone();
+
+        // negotiation is needed again once the current one is done
+        if peer_connection.signaling_state() != RTCSignalingState::Stable {
+            peer.offer_pending = true;
+            return Ok(());
+        }
+
+        // clients from before the mesh don't know how to ask, so they still get offers
+        if peer.polite && peer.negotiated && !participant.session.is_empty() {
+            return self
+                .send_signal(Some(participant), SignalMessage::Renegotiate)
+                .await;
+        }
+
+        let offer = peer_connection
+            .create_offer(None)
+            .await
+            .map_err(|e| Error::WebRTC { error: e })?;
+        peer_connection
+            .set_local_description(offer.clone())
+            .await
+            .map_err(|e| Error::WebRTC { error: e })?;
+
+        self.send_signal(Some(participant), SignalMessage::Offer { payload: offer })
+            .await
+    }
+
+    async fn handle_offer(
+        &mut self,
+        participant: &Participant,
+        offer: &RTCSessionDescription,
+    ) -> Result<(), Error> {
+        let mut peer_connection = self.peer_connection_for(participant).await?;
+        if let Some(current) = peer_connection.remote_description().await
+            && fingerprint(&current.sdp) != fingerprint(&offer.sdp)
+        {
+            info!("{} reconnected, replacing peer connection", participant);
+            self.remove_peer(participant).await;
+            peer_connection = self.add_peer(participant).await?;
+        }
+
+        let peer = match self.peer_mut(participant) {
+            Some(peer) => peer,
             None => return Err(Error::PeerConnectionNotReady),
         };
 
+        // offers are only made while holding the client, so an offer of ours that is still in
+        // flight shows up as a signaling state other than stable
+        let offer_collision = peer_connection.signaling_state() != RTCSignalingState::Stable;
+        peer.ignore_offer = !peer.polite && offer_collision;
+        if peer.ignore_offer {
+            info!("ignoring colliding offer from {}", participant);
+            return Ok(());
+        }
+
+        if offer_collision {
+            let mut rollback = RTCSessionDescription::default();
+            rollback.sdp_type = RTCSdpType::Rollback;
+            // the sdp of a rollback is ignored, but it still has to parse
+            rollback.sdp = peer_connection
+                .pending_local_description()
+                .await
+                .map(|description| description.sdp)
+                .unwrap_or_default();
+            // webrtc-rs doesn't allow rolling back from have-local-offer yet, so start over with
+            // a fresh connection, which amounts to the same thing as long as the first
+            // negotiation hasn't completed. After that it would drop the call, but then only
+            // clients from before the mesh still make offers that can collide with ours
+            if let Err(e) = peer_connection.set_local_description(rollback).await {
+                if self.peer(participant).is_some_and(|peer| peer.negotiated) {
+                    return Err(Error::WebRTC { error: e });
+                }
+
+                info!(
+                    "unable to roll back offer to {}, reconnecting: {}",
+                    participant, e
+                );
+                self.remove_peer(participant).await;
+                peer_connection = self.add_peer(participant).await?;
+            }
+        }
+
         peer_connection
             .set_remote_description(offer.clone())
             .await
             .map_err(|e| Error::WebRTC { error: e })?;
+        self.add_pending_candidates(participant).await;
 
         let answer = peer_connection
             .create_answer(None)
@@ -185,38 +975,282 @@ impl SignalHandler for Client {
             .await
             .map_err(|e| Error::WebRTC { error: e })?;
 
-        let message = SignalMessage::Answer {
-            payload: answer.clone(),
-        };
-        let json_string = match serde_json::to_string(&message) {
-            Ok(json) => json,
-            Err(_) => return Err(Error::Serialization),
+        self.send_signal(Some(participant), SignalMessage::Answer { payload: answer })
+            .await?;
+        self.finish_negotiation(participant).await
+    }
+
+    async fn handle_answer(
+        &mut self,
+        participant: &Participant,
+        answer: &RTCSessionDescription,
+    ) -> Result<(), Error> {
+        let peer = match self.peer(participant) {
+            Some(peer) => peer,
+            None => return Err(Error::PeerConnectionNotReady),
         };
 
-        self.send_message(json_string).await
+        peer.peer_connection
+            .set_remote_description(answer.clone())
+            .await
+            .map_err(|e| Error::WebRTC { error: e })?;
+        self.add_pending_candidates(participant).await;
+        self.finish_negotiation(participant).await
     }
 
-    async fn handle_answer(&mut self, answer: &RTCSessionDescription) -> Result<(), Error> {
-        let peer_connection = match &self.peer_connection {
-            Some(peer_connection) => peer_connection,
+    async fn handle_candidate(
+        &mut self,
+        participant: &Participant,
+        candidate: &RTCIceCandidateInit,
+    ) -> Result<(), Error> {
+        // candidates can overtake the offer they belong to
+        self.peer_connection_for(participant).await?;
+        let peer = match self.peer_mut(participant) {
+            Some(peer) => peer,
             None => return Err(Error::PeerConnectionNotReady),
         };
+        if peer.peer_connection.remote_description().await.is_none() {
+            peer.pending_candidates.push(candidate.clone());
+            return Ok(());
+        }
 
-        peer_connection
-            .set_remote_description(answer.clone())
+        match peer
+            .peer_connection
+            .add_ice_candidate(candidate.clone())
             .await
-            .map_err(|e| Error::WebRTC { error: e })
+        {
+            Ok(()) => Ok(()),
+            // candidates for an offer that we ignored
+            Err(_) if peer.ignore_offer => Ok(()),
+            Err(e) => Err(Error::WebRTC { error: e }),
+        }
     }
 
-    async fn handle_candidate(&mut self, candidate: &RTCIceCandidateInit) -> Result<(), Error> {
-        let peer_connection = match &self.peer_connection {
-            Some(peer_connection) => peer_connection,
+    // the polite side changed something, and leaves the offer to us
+    async fn handle_renegotiate(&mut self, participant: &Participant) -> Result<(), Error> {
+        let peer_connection = match self.peer(participant) {
+            Some(peer) if peer.polite => return Ok(()),
+            Some(peer) => peer.peer_connection.clone(),
             None => return Err(Error::PeerConnectionNotReady),
         };
 
+        // a track that the peer added is only negotiated if our offer has room for it
+        let has_video = peer_connection
+            .get_transceivers()
+            .await
+            .iter()
+            .any(|transceiver| transceiver.kind() == RTPCodecType::Video);
+        if !has_video {
+            let init = RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                send_encodings: Vec::new(),
+            };
+            peer_connection
+                .add_transceiver_from_kind(RTPCodecType::Video, Some(init))
+                .await
+                .map_err(|e| Error::WebRTC { error: e })?;
+        }
+
+        self.send_offer(participant).await
+    }
+
+    async fn send_viewport(&mut self) -> Result<(), Error> {
+        let viewport = match &self.viewport {
+            Some(viewport) => viewport.clone(),
+            None => return Ok(()),
+        };
+
+        self.send_signal(None, SignalMessage::Viewport { payload: viewport })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{accept_async, connect_async};
+    use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+
+    use super::*;
+
+    async fn new_client() -> Arc<Mutex<Client>> {
+        let client = Arc::new(Mutex::new(Client::new()));
+        client.lock().await.weak_self = Arc::downgrade(&client);
+        client
+    }
+
+    // stands in for the server, handing every signal that one client sends to the other
+    async fn relay(sender: &Arc<Mutex<Client>>, receiver: &Arc<Mutex<Client>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept_async(stream).await.unwrap()
+        });
+        let (stream, _) = connect_async(url).await.unwrap();
+        let (write_stream, _) = stream.split();
+        sender.lock().await.write_stream = Some(write_stream);
+
+        let mut server = accept.await.unwrap();
+        let receiver = Arc::downgrade(receiver);
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = server.next().await {
+                let signal = match serde_json::from_str(message.to_text().unwrap_or_default()) {
+                    Ok(Message::Signal {
+                        from,
+                        to,
+                        signal_message,
+                    }) => (from, to, signal_message),
+                    _ => continue,
+                };
+                let client = match receiver.upgrade() {
+                    Some(client) => client,
+                    None => return,
+                };
+                let mut client = client.lock().await;
+                if let Err(e) = client
+                    .receive_signal(signal.0.as_ref(), signal.1.as_ref(), &signal.2)
+                    .await
+                {
+                    info!("unable to handle signal: {}", e);
+                }
+            }
+        });
+    }
+
+    // whether negotiation has settled, with every transceiver on both sides negotiated
+    async fn settled(client: &Arc<Mutex<Client>>, participant: &Participant) -> bool {
+        let client = client.lock().await;
+        let peer = match client.peer(participant) {
+            Some(peer) => peer,
+            None => return false,
+        };
+        let open = peer
+            .chat_channel()
+            .is_some_and(|channel| channel.ready_state() == RTCDataChannelState::Open);
+        let mut negotiated = peer.negotiated
+            && !peer.offer_pending
+            && peer.peer_connection.signaling_state() == RTCSignalingState::Stable;
+        for transceiver in peer.peer_connection.get_transceivers().await {
+            negotiated &= transceiver.mid().is_some();
+        }
+        open && negotiated
+    }
+
+    async fn wait_until_settled(
+        a: &Arc<Mutex<Client>>,
+        b: &Arc<Mutex<Client>>,
+        a_participant: &Participant,
+        b_participant: &Participant,
+    ) {
+        let wait = async {
+            while !(settled(a, b_participant).await && settled(b, a_participant).await) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        timeout(Duration::from_secs(20), wait)
+            .await
+            .expect("negotiation didn't settle");
+    }
+
+    async fn add_video(client: &Arc<Mutex<Client>>, participant: &Participant) {
+        let peer_connection = client
+            .lock()
+            .await
+            .peer(participant)
+            .unwrap()
+            .peer_connection
+            .clone();
         peer_connection
-            .add_ice_candidate(candidate.clone())
+            .add_transceiver_from_kind(RTPCodecType::Video, None)
+            .await
+            .unwrap();
+    }
+
+    fn participant(username: &str, session: &str) -> Participant {
+        Participant {
+            username: username.to_owned(),
+            session: session.to_owned(),
+        }
+    }
+
+    #[test]
+    fn track_participant_from_label() {
+        let alice = participant("alice", "a1");
+        assert_eq!(track_participant(alice.clone(), &alice), alice);
+
+        // the connection is with a client that didn't say its session
+        let legacy = participant("alice", "");
+        assert_eq!(track_participant(alice.clone(), &legacy), alice);
+    }
+
+    #[test]
+    fn track_participant_falls_back_to_connection() {
+        let alice = participant("alice", "a1");
+
+        // fixed ids from older clients
+        assert_eq!(
+            track_participant(participant("webrtc-rs", "video"), &alice),
+            alice
+        );
+        assert_eq!(track_participant(participant("alice", ""), &alice), alice);
+        // someone else's name, or another client of the same user
+        assert_eq!(track_participant(participant("bob", "b1"), &alice), alice);
+        assert_eq!(track_participant(participant("alice", "a2"), &alice), alice);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn glare_keeps_data_channels() {
+        let a = new_client().await;
+        let b = new_client().await;
+        relay(&a, &b).await;
+        relay(&b, &a).await;
+        let a_participant = a.lock().await.participant();
+        let b_participant = b.lock().await.participant();
+
+        a.lock()
             .await
-            .map_err(|e| Error::WebRTC { error: e })
+            .connect_to_peer(&b_participant)
+            .await
+            .unwrap();
+        b.lock()
+            .await
+            .connect_to_peer(&a_participant)
+            .await
+            .unwrap();
+        wait_until_settled(&a, &b, &a_participant, &b_participant).await;
+
+        let peer_state = |client: &Arc<Mutex<Client>>, participant: &Participant| {
+            let client = client.clone();
+            let participant = participant.clone();
+            async move {
+                let client = client.lock().await;
+                let peer = client.peer(&participant).unwrap();
+                (peer.peer_connection.clone(), peer.chat_channel().unwrap())
+            }
+        };
+        let (a_connection, a_channel) = peer_state(&a, &b_participant).await;
+        let (b_connection, b_channel) = peer_state(&b, &a_participant).await;
+
+        // both sides change something and try to offer at the same time
+        add_video(&a, &b_participant).await;
+        add_video(&b, &a_participant).await;
+        let (a_offer, b_offer) = tokio::join!(
+            async { a.lock().await.send_offer(&b_participant).await },
+            async { b.lock().await.send_offer(&a_participant).await },
+        );
+        a_offer.unwrap();
+        b_offer.unwrap();
+        wait_until_settled(&a, &b, &a_participant, &b_participant).await;
+
+        let (a_connection_after, a_channel_after) = peer_state(&a, &b_participant).await;
+        let (b_connection_after, b_channel_after) = peer_state(&b, &a_participant).await;
+        assert!(Arc::ptr_eq(&a_connection, &a_connection_after));
+        assert!(Arc::ptr_eq(&b_connection, &b_connection_after));
+        assert!(Arc::ptr_eq(&a_channel, &a_channel_after));
+        assert!(Arc::ptr_eq(&b_channel, &b_channel_after));
+        assert_eq!(a_channel.ready_state(), RTCDataChannelState::Open);
+        assert_eq!(b_channel.ready_state(), RTCDataChannelState::Open);
     }
 }
@@ -0,0 +1,661 @@
+use std::{
+    collections::HashSet,
+    env,
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, native_pty_system};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tracing::info;
+use webrtc::{
+    data_channel::{
+        RTCDataChannel, data_channel_init::RTCDataChannelInit,
+        data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState,
+    },
+    peer_connection::RTCPeerConnection,
+};
+
+use crate::client::{Client, error::Error, message::Participant};
+
+pub(crate) const TERMINAL_CHANNEL_LABEL: &str = "terminal";
+
+const DEFAULT_COLUMNS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+const MIN_COLUMNS: u16 = 20;
+const MIN_ROWS: u16 = 5;
+// larger than any panel, and small enough that a peer can't make us allocate a huge screen
+const MAX_COLUMNS: u16 = 500;
+const MAX_ROWS: u16 = 500;
+// data channel messages are limited to 16 KiB
+const CHUNK_SIZE: usize = 16 * 1024;
+const READ_BUFFER_SIZE: usize = 4096;
+// a viewer this far behind gets the whole screen again once it catches up, instead of
+// everything that was printed in between
+const BUFFERED_AMOUNT_HIGH: usize = 1024 * 1024;
+// resets the viewer's terminal before the screen is sent again
+const RESET: &[u8] = b"\x1bc";
+
+// Output is sent as binary messages, and everything else as text
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+enum TerminalMessage {
+    #[serde(rename = "terminal_size")]
+    Size { columns: u16, rows: u16 },
+
+    #[serde(rename = "terminal_control")]
+    Control { granted: bool },
+
+    // only acted on while control is granted to the sender
+    #[serde(rename = "terminal_input")]
+    Input { data: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalOwner {
+    Local,
+    Peer(Participant),
+}
+
+#[derive(Debug)]
+pub enum TerminalEvent {
+    Output {
+        owner: TerminalOwner,
+        data: Vec<u8>,
+    },
+    Resize {
+        owner: TerminalOwner,
+        columns: u16,
+        rows: u16,
+    },
+    Control {
+        from: Participant,
+        granted: bool,
+    },
+    Ended {
+        owner: TerminalOwner,
+    },
+}
+
+struct Viewer {
+    participant: Participant,
+    data_channel: Arc<RTCDataChannel>,
+    behind: bool,
+}
+
+// The screen is kept along with the viewers, so that a viewer that joins late gets
+// everything up to the point where it starts receiving output
+struct Screen {
+    parser: vt100::Parser,
+    viewers: Vec<Viewer>,
+}
+
+// A terminal that we share with the room
+pub(crate) struct SharedTerminal {
+    columns: u16,
+    rows: u16,
+    screen: tokio::sync::Mutex<Screen>,
+    // written to the pty by a thread of its own
+    input: UnboundedSender<Vec<u8>>,
+    child: Mutex<Box<dyn Child + Send + Sync>>,
+    // the pty goes away with the master
+    _master: Mutex<Box<dyn MasterPty + Send>>,
+    // peers that can type into the terminal
+    granted: Mutex<HashSet<Participant>>,
+    events: UnboundedSender<TerminalEvent>,
+}
+
+impl SharedTerminal {
+    fn spawn(
+        command: Option<&str>,
+        columns: u16,
+        rows: u16,
+        events: UnboundedSender<TerminalEvent>,
+    ) -> Result<Arc<Self>, Error> {
+        let spawn_error = |e: String| Error::SpawnTerminal { error: e };
+
+        let pair = native_pty_system()
+            .openpty(PtySize {
+                rows: rows,
+                cols: columns,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| spawn_error(e.to_string()))?;
+
+        let mut command_builder = match command {
+            Some(command) => shell_command(command),
+            None => CommandBuilder::new_default_prog(),
+        };
+        if let Ok(current_dir) = env::current_dir() {
+            command_builder.cwd(current_dir);
+        }
+        command_builder.env("TERM", "xterm-256color");
+
+        let child = pair
+            .slave
+            .spawn_command(command_builder)
+            .map_err(|e| spawn_error(e.to_string()))?;
+        // otherwise reading never ends, since the slave stays open after the child exits
+        drop(pair.slave);
+
+        let mut reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| spawn_error(e.to_string()))?;
+        let mut writer = pair
+            .master
+            .take_writer()
+            .map_err(|e| spawn_error(e.to_string()))?;
+
+        // writing to the pty blocks too, while the command doesn't read its input
+        let (input, mut input_receiver) = unbounded_channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            while let Some(data) = input_receiver.blocking_recv() {
+                if writer
+                    .write_all(&data)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        let shared_terminal = Arc::new(Self {
+            columns: columns,
+            rows: rows,
+            screen: tokio::sync::Mutex::new(Screen {
+                parser: vt100::Parser::new(rows, columns, 0),
+                viewers: Vec::new(),
+            }),
+            input: input,
+            child: Mutex::new(child),
+            _master: Mutex::new(pair.master),
+            granted: Mutex::new(HashSet::new()),
+            events: events,
+        });
+        shared_terminal.event(TerminalEvent::Resize {
+            owner: TerminalOwner::Local,
+            columns: columns,
+            rows: rows,
+        });
+
+        // reading from the pty blocks
+        let (output_sender, mut output_receiver) = unbounded_channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; READ_BUFFER_SIZE];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(read) => {
+                        if output_sender.send(buffer[..read].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        let output_terminal = shared_terminal.clone();
+        tokio::spawn(async move {
+            while let Some(data) = output_receiver.recv().await {
+                output_terminal.output(data).await;
+            }
+            output_terminal.end().await;
+        });
+
+        Ok(shared_terminal)
+    }
+
+    fn event(&self, event: TerminalEvent) {
+        let _ = self.events.send(event);
+    }
+
+    async fn output(&self, data: Vec<u8>) {
+        let mut screen = self.screen.lock().await;
+        let Screen { parser, viewers } = &mut *screen;
+        parser.process(&data);
+
+        for viewer in viewers.iter_mut() {
+            let buffered_amount = viewer.data_channel.buffered_amount().await;
+            if buffered_amount > BUFFERED_AMOUNT_HIGH {
+                viewer.behind = true;
+                continue;
+            }
+
+            let result = if viewer.behind {
+                viewer.behind = false;
+                send_screen(&viewer.data_channel, &parser.screen().state_formatted()).await
+            } else {
+                viewer
+                    .data_channel
+                    .send(&Bytes::copy_from_slice(&data))
+                    .await
+                    .map(|_| ())
+            };
+            if let Err(e) = result {
+                info!("unable to send terminal output: {}", e);
+            }
+        }
+        viewers.retain(|viewer| viewer.data_channel.ready_state() != RTCDataChannelState::Closed);
+        drop(screen);
+
+        self.event(TerminalEvent::Output {
+            owner: TerminalOwner::Local,
+            data: data,
+        });
+    }
+
+    // the command exited, or sharing was stopped
+    async fn end(&self) {
+        let viewers = std::mem::take(&mut self.screen.lock().await.viewers);
+        for viewer in viewers {
+            let _ = viewer.data_channel.close().await;
+        }
+        self.event(TerminalEvent::Ended {
+            owner: TerminalOwner::Local,
+        });
+    }
+
+    fn stop(&self) {
+        if let Ok(mut child) = self.child.lock()
+            && let Err(e) = child.kill()
+        {
+            info!("unable to stop the shared terminal: {}", e);
+        }
+    }
+
+    fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.input.send(data).map_err(|_| Error::NotSharingTerminal)
+    }
+
+    fn is_granted(&self, participant: &Participant) -> bool {
+        self.granted
+            .lock()
+            .is_ok_and(|granted| granted.contains(participant))
+    }
+
+    // takes control away if all of them have it, and otherwise gives it to all of them
+    fn toggle_control(&self, participants: &[Participant]) -> bool {
+        let mut granted = match self.granted.lock() {
+            Ok(granted) => granted,
+            Err(_) => return false,
+        };
+        if participants
+            .iter()
+            .all(|participant| granted.contains(participant))
+        {
+            for participant in participants {
+                granted.remove(participant);
+            }
+            false
+        } else {
+            granted.extend(participants.iter().cloned());
+            true
+        }
+    }
+
+    async fn send_control(&self) {
+        for viewer in self.screen.lock().await.viewers.iter() {
+            let message = TerminalMessage::Control {
+                granted: self.is_granted(&viewer.participant),
+            };
+            let _ = send_message(&viewer.data_channel, &message).await;
+        }
+    }
+
+    // opens a channel to a peer, which then gets the size and screen so far once it's open
+    async fn add_viewer(
+        self: &Arc<Self>,
+        participant: &Participant,
+        peer_connection: &RTCPeerConnection,
+    ) -> Result<(), Error> {
+        let options = RTCDataChannelInit {
+            ordered: Some(true),
+            ..Default::default()
+        };
+        let data_channel = peer_connection
+            .create_data_channel(TERMINAL_CHANNEL_LABEL, Some(options))
+            .await
+            .map_err(|e| Error::WebRTC { error: e })?;
+
+        let open_terminal = Arc::downgrade(self);
+        let open_participant = participant.clone();
+        let weak_data_channel = Arc::downgrade(&data_channel);
+        data_channel.on_open(Box::new(move || {
+            let participant = open_participant.clone();
+            Box::pin(async move {
+                let (shared_terminal, data_channel) =
+                    match (open_terminal.upgrade(), weak_data_channel.upgrade()) {
+                        (Some(shared_terminal), Some(data_channel)) => {
+                            (shared_terminal, data_channel)
+                        }
+                        _ => return,
+                    };
+                shared_terminal.watch(participant, data_channel).await;
+            })
+        }));
+
+        let input_terminal = Arc::downgrade(self);
+        let input_participant = participant.clone();
+        data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let shared_terminal = match input_terminal.upgrade() {
+                Some(shared_terminal) => shared_terminal,
+                None => return Box::pin(async {}),
+            };
+
+            match serde_json::from_slice::<TerminalMessage>(&message.data) {
+                Ok(TerminalMessage::Input { data }) => {
+                    if shared_terminal.is_granted(&input_participant) {
+                        let _ = shared_terminal.write(data);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => info!("invalid terminal message from {}: {}", input_participant, e),
+            }
+            Box::pin(async {})
+        }));
+
+        Ok(())
+    }
+
+    async fn watch(&self, participant: Participant, data_channel: Arc<RTCDataChannel>) {
+        let mut screen = self.screen.lock().await;
+
+        let size = TerminalMessage::Size {
+            columns: self.columns,
+            rows: self.rows,
+        };
+        let result = match send_message(&data_channel, &size).await {
+            Ok(()) => send_screen(&data_channel, &screen.parser.screen().state_formatted()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            info!("unable to start sending the terminal: {}", e);
+            return;
+        }
+
+        if self.is_granted(&participant) {
+            let _ = send_message(&data_channel, &TerminalMessage::Control { granted: true }).await;
+        }
+        screen.viewers.push(Viewer {
+            participant: participant,
+            data_channel: data_channel,
+            behind: false,
+        });
+    }
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> CommandBuilder {
+    let shell = env::var("SHELL").unwrap_or_else(|_| String::from("sh"));
+    let mut command_builder = CommandBuilder::new(shell);
+    command_builder.arg("-c");
+    command_builder.arg(command);
+    command_builder
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> CommandBuilder {
+    let mut command_builder = CommandBuilder::new("cmd");
+    command_builder.arg("/C");
+    command_builder.arg(command);
+    command_builder
+}
+
+async fn send_message(
+    data_channel: &RTCDataChannel,
+    message: &TerminalMessage,
+) -> Result<(), webrtc::Error> {
+    let json_string = serde_json::to_string(message).unwrap_or_default();
+    data_channel.send_text(json_string).await.map(|_| ())
+}
+
+async fn send_screen(data_channel: &RTCDataChannel, state: &[u8]) -> Result<(), webrtc::Error> {
+    data_channel.send(&Bytes::from_static(RESET)).await?;
+    for chunk in state.chunks(CHUNK_SIZE) {
+        data_channel.send(&Bytes::copy_from_slice(chunk)).await?;
+    }
+    Ok(())
+}
+
+// the emulator can't handle an empty screen, and has to allocate every cell of a big one
+fn peer_size(columns: u16, rows: u16) -> Option<(u16, u16)> {
+    if columns == 0 || rows == 0 {
+        return None;
+    }
+    Some((columns.min(MAX_COLUMNS), rows.min(MAX_ROWS)))
+}
+
+pub(crate) fn receive_terminal_channel(
+    participant: Participant,
+    data_channel: &Arc<RTCDataChannel>,
+    events: Option<UnboundedSender<TerminalEvent>>,
+) {
+    let events = match events {
+        Some(events) => events,
+        None => return,
+    };
+
+    let close_participant = participant.clone();
+    let close_events = events.clone();
+    data_channel.on_close(Box::new(move || {
+        let _ = close_events.send(TerminalEvent::Ended {
+            owner: TerminalOwner::Peer(close_participant.clone()),
+        });
+        Box::pin(async {})
+    }));
+
+    data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let owner = TerminalOwner::Peer(participant.clone());
+        if !message.is_string {
+            let _ = events.send(TerminalEvent::Output {
+                owner: owner,
+                data: message.data.to_vec(),
+            });
+            return Box::pin(async {});
+        }
+
+        match serde_json::from_slice::<TerminalMessage>(&message.data) {
+            Ok(TerminalMessage::Size { columns, rows }) => match peer_size(columns, rows) {
+                Some((columns, rows)) => {
+                    let _ = events.send(TerminalEvent::Resize {
+                        owner: owner,
+                        columns: columns,
+                        rows: rows,
+                    });
+                }
+                None => info!(
+                    "ignoring terminal size {}x{} from {}",
+                    columns, rows, participant
+                ),
+            },
+            Ok(TerminalMessage::Control { granted }) => {
+                let _ = events.send(TerminalEvent::Control {
+                    from: participant.clone(),
+                    granted: granted,
+                });
+            }
+            Ok(TerminalMessage::Input { .. }) => {}
+            Err(e) => info!("invalid terminal message from {}: {}", participant, e),
+        }
+        Box::pin(async {})
+    }));
+}
+
+// Output of our own terminal and the ones peers share is handed to the UI as it arrives
+pub async fn init_terminal(
+    client: &Arc<tokio::sync::Mutex<Client>>,
+) -> UnboundedReceiver<TerminalEvent> {
+    let (sender, receiver) = unbounded_channel();
+    client.lock().await.terminal_sender = Some(sender);
+    receiver
+}
+
+impl Client {
+    // sized to fit the smallest panel it's going to be shown in
+    fn terminal_size(&self) -> (u16, u16) {
+        let columns = self
+            .peer_viewports
+            .values()
+            .map(|viewport| viewport.width)
+            .min();
+        let rows = self
+            .peer_viewports
+            .values()
+            .map(|viewport| viewport.height / 2)
+            .min();
+
+        match (columns, rows) {
+            (Some(columns), Some(rows)) => (
+                (columns as u16).clamp(MIN_COLUMNS, MAX_COLUMNS),
+                (rows as u16).clamp(MIN_ROWS, MAX_ROWS),
+            ),
+            _ => (DEFAULT_COLUMNS, DEFAULT_ROWS),
+        }
+    }
+
+    pub(crate) async fn share_terminal(&mut self, command: Option<&str>) -> Result<String, Error> {
+        if self.viewer {
+            return Err(Error::Viewer);
+        }
+        if self.shared_terminal.is_some() {
+            return Err(Error::AlreadySharingTerminal);
+        }
+        let events = match &self.terminal_sender {
+            Some(events) => events.clone(),
+            None => return Err(Error::NotSharingTerminal),
+        };
+
+        let (columns, rows) = self.terminal_size();
+        let shared_terminal = SharedTerminal::spawn(command, columns, rows, events)?;
+        for (participant, peer) in &self.peers {
+            if let Err(e) = shared_terminal
+                .add_viewer(participant, &peer.peer_connection)
+                .await
+            {
+                info!("unable to share the terminal with {}: {}", participant, e);
+            }
+        }
+        self.shared_terminal = Some(shared_terminal);
+
+        Ok(format!(
+            "sharing {} ({}x{}), /grant [username] lets others type, /unshare stops",
+            command.unwrap_or("a shell"),
+            columns,
+            rows
+        ))
+    }
+
+    // e.g. for a peer that joins while the terminal is shared
+    pub(crate) async fn share_terminal_with(
+        &self,
+        participant: &Participant,
+        peer_connection: &RTCPeerConnection,
+    ) -> Result<(), Error> {
+        match &self.shared_terminal {
+            Some(shared_terminal) => {
+                shared_terminal
+                    .add_viewer(participant, peer_connection)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn unshare_terminal(&mut self) -> Result<String, Error> {
+        let shared_terminal = self
+            .shared_terminal
+            .take()
+            .ok_or(Error::NotSharingTerminal)?;
+        shared_terminal.stop();
+        Ok(String::from("stopped sharing the terminal"))
+    }
+
+    // once the command exits by itself, returning whether it was still shared until then
+    pub fn terminal_ended(&mut self) -> bool {
+        self.shared_terminal.take().is_some()
+    }
+
+    // for everyone that's in the room right now, or everyone with the username. Peers that
+    // join later don't get to type until they're granted too
+    pub(crate) async fn grant_terminal(&mut self, username: Option<&str>) -> Result<String, Error> {
+        let shared_terminal = self
+            .shared_terminal
+            .clone()
+            .ok_or(Error::NotSharingTerminal)?;
+        let participants = self
+            .peers
+            .keys()
+            .filter(|participant| username.is_none_or(|username| participant.username == username))
+            .cloned()
+            .collect::<Vec<_>>();
+        if participants.is_empty() {
+            return match username {
+                Some(username) => Err(Error::UnknownParticipant {
+                    username: username.to_owned(),
+                }),
+                None => Err(Error::NoParticipants),
+            };
+        }
+
+        let granted = shared_terminal.toggle_control(&participants);
+        shared_terminal.send_control().await;
+
+        let who = username.unwrap_or("others");
+        if granted {
+            Ok(format!("{} can now type into the terminal", who))
+        } else {
+            Ok(format!("{} can no longer type into the terminal", who))
+        }
+    }
+
+    pub async fn send_terminal_input(
+        &self,
+        owner: &TerminalOwner,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let participant = match owner {
+            TerminalOwner::Local => {
+                return match &self.shared_terminal {
+                    Some(shared_terminal) => shared_terminal.write(data),
+                    None => Err(Error::NotSharingTerminal),
+                };
+            }
+            TerminalOwner::Peer(participant) => participant,
+        };
+
+        let terminal_channel = match self
+            .peer(participant)
+            .and_then(|peer| peer.terminal_channel())
+        {
+            Some(terminal_channel)
+                if terminal_channel.ready_state() == RTCDataChannelState::Open =>
+            {
+                terminal_channel
+            }
+            _ => return Err(Error::NoDataChannel),
+        };
+        send_message(&terminal_channel, &TerminalMessage::Input { data: data })
+            .await
+            .map_err(|e| Error::WebRTC { error: e })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_sizes() {
+        assert_eq!(peer_size(80, 24), Some((80, 24)));
+        assert_eq!(peer_size(1, 1), Some((1, 1)));
+        assert_eq!(peer_size(0, 24), None);
+        assert_eq!(peer_size(80, 0), None);
+        assert_eq!(peer_size(0, 0), None);
+        assert_eq!(peer_size(u16::MAX, u16::MAX), Some((MAX_COLUMNS, MAX_ROWS)));
+    }
+}
@@ -0,0 +1,942 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{
+        Notify,
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    },
+    time::timeout,
+};
+use tracing::info;
+use webrtc::{
+    data_channel::{
+        RTCDataChannel, data_channel_init::RTCDataChannelInit,
+        data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState,
+    },
+    peer_connection::RTCPeerConnection,
+};
+
+use crate::client::{Client, data_channel::ChannelMessage, error::Error, message::Participant};
+
+pub(crate) const FILE_CHANNEL_PREFIX: &str = "file:";
+
+// data channel messages are limited to 16 KiB
+const CHUNK_SIZE: usize = 16 * 1024;
+const BUFFERED_AMOUNT_HIGH: usize = 1024 * 1024;
+const BUFFERED_AMOUNT_LOW: usize = 256 * 1024;
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    static ref DOWNLOAD_DIR: PathBuf = env::var("DOWNLOAD_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("."));
+}
+
+// Sent over the chat channel, while the file itself gets a channel of its own
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum TransferMessage {
+    #[serde(rename = "file_offer")]
+    Offer {
+        id: String,
+        name: String,
+        size: u64,
+        sha256: String,
+    },
+
+    // also sent to resume an interrupted transfer, which is why it carries an offset
+    #[serde(rename = "file_accept")]
+    Accept { id: String, offset: u64 },
+
+    #[serde(rename = "file_reject")]
+    Reject { id: String },
+}
+
+#[derive(Debug)]
+pub enum TransferEvent {
+    Info(String),
+    Error(String),
+    Progress {
+        key: String,
+        description: String,
+        done: u64,
+        size: u64,
+    },
+    // the progress bar with the key can go
+    Ended {
+        key: String,
+    },
+}
+
+struct OutgoingFile {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    // who it was offered to, since nobody else gets to accept it
+    recipients: Vec<Participant>,
+}
+
+struct IncomingFile {
+    from: Participant,
+    name: String,
+    size: u64,
+    sha256: String,
+    received: u64,
+    accepted: bool,
+    // a file channel is currently open for it
+    active: bool,
+}
+
+// Files we offered and files offered to us, shared with the data channel callbacks
+pub(crate) struct Transfers {
+    outgoing: Mutex<HashMap<String, OutgoingFile>>,
+    incoming: Mutex<HashMap<String, IncomingFile>>,
+    // offers that haven't been accepted or rejected yet, oldest first
+    offers: Mutex<VecDeque<String>>,
+    events: UnboundedSender<TransferEvent>,
+    download_dir: PathBuf,
+}
+
+// What it takes to send a file over a channel of its own and report on how it's going
+struct TransferContext {
+    transfers: Arc<Transfers>,
+    id: String,
+    path: PathBuf,
+    size: u64,
+    key: String,
+    description: String,
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1_000 => format!("{} B", bytes),
+        1_000..1_000_000 => format!("{:.1} KB", bytes as f64 / 1_000.0),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1_000_000.0),
+        _ => format!("{:.1} GB", bytes as f64 / 1_000_000_000.0),
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+pub(crate) async fn hash(path: &Path) -> std::io::Result<String> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+// ids end up in file names, so only the ones we'd make ourselves are accepted
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// names come from peers, so anything that looks like a path is dropped
+fn sanitize_name(name: &str) -> String {
+    let name = Path::new(name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .trim_start_matches('.');
+    match name.is_empty() {
+        true => String::from("download"),
+        false => name.to_owned(),
+    }
+}
+
+impl Transfers {
+    pub(crate) fn new(events: UnboundedSender<TransferEvent>) -> Self {
+        Self {
+            outgoing: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(HashMap::new()),
+            offers: Mutex::new(VecDeque::new()),
+            events: events,
+            download_dir: DOWNLOAD_DIR.clone(),
+        }
+    }
+
+    // The partial file sticks around after an interruption, so that the transfer can resume
+    fn partial_path(&self, id: &str, name: &str) -> PathBuf {
+        self.download_dir.join(format!(".{}.{}.part", name, id))
+    }
+
+    // doesn't overwrite files that are already there
+    fn download_path(&self, name: &str) -> PathBuf {
+        let path = self.download_dir.join(name);
+        if !path.exists() {
+            return path;
+        }
+
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+            _ => (name, String::new()),
+        };
+        (1..)
+            .map(|n| {
+                self.download_dir
+                    .join(format!("{} ({}){}", stem, n, extension))
+            })
+            .find(|path| !path.exists())
+            .unwrap_or(path)
+    }
+
+    async fn open_partial(
+        &self,
+        id: &str,
+        name: &str,
+        offset: u64,
+    ) -> std::io::Result<tokio::fs::File> {
+        tokio::fs::create_dir_all(&self.download_dir).await?;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.partial_path(id, name))
+            .await?;
+        // anything past the offset didn't make it before the interruption
+        file.set_len(offset).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        Ok(file)
+    }
+
+    fn event(&self, event: TransferEvent) {
+        let _ = self.events.send(event);
+    }
+
+    pub(crate) fn add_outgoing(
+        &self,
+        id: &str,
+        path: PathBuf,
+        name: &str,
+        size: u64,
+        recipients: Vec<Participant>,
+    ) {
+        if let Ok(mut outgoing) = self.outgoing.lock() {
+            outgoing.insert(
+                id.to_owned(),
+                OutgoingFile {
+                    path: path,
+                    name: name.to_owned(),
+                    size: size,
+                    recipients: recipients,
+                },
+            );
+        }
+    }
+
+    // the next offer to accept or reject, and who it's from
+    pub(crate) fn next_offer(&self) -> Option<(String, Participant)> {
+        let offers = self.offers.lock().ok()?;
+        let incoming = self.incoming.lock().ok()?;
+        offers.iter().find_map(|id| {
+            incoming
+                .get(id)
+                .map(|incoming_file| (id.clone(), incoming_file.from.clone()))
+        })
+    }
+
+    // returns the offset to start at
+    pub(crate) fn accept(&self, id: &str) -> Option<u64> {
+        if let Ok(mut offers) = self.offers.lock() {
+            offers.retain(|offer| offer != id);
+        }
+
+        let mut incoming = self.incoming.lock().ok()?;
+        let incoming_file = incoming.get_mut(id)?;
+        incoming_file.accepted = true;
+        Some(incoming_file.received)
+    }
+
+    pub(crate) fn reject(&self, id: &str) -> Option<String> {
+        if let Ok(mut offers) = self.offers.lock() {
+            offers.retain(|offer| offer != id);
+        }
+
+        let mut incoming = self.incoming.lock().ok()?;
+        incoming.remove(id).map(|incoming_file| incoming_file.name)
+    }
+
+    // transfers from the participant that were cut off, and where to pick them up
+    pub(crate) fn interrupted(&self, participant: &Participant) -> Vec<(String, u64)> {
+        match self.incoming.lock() {
+            Ok(incoming) => incoming
+                .iter()
+                .filter(|(_, incoming_file)| {
+                    incoming_file.from.matches(participant)
+                        && incoming_file.accepted
+                        && !incoming_file.active
+                })
+                .map(|(id, incoming_file)| (id.clone(), incoming_file.received))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub(crate) fn receive_message(
+        self: &Arc<Self>,
+        participant: &Participant,
+        message: TransferMessage,
+        weak_peer_connection: Weak<RTCPeerConnection>,
+    ) {
+        match message {
+            TransferMessage::Offer {
+                id,
+                name,
+                size,
+                sha256,
+            } => {
+                if !valid_id(&id) {
+                    info!("ignoring file offer from {} with id {:?}", participant, id);
+                    return;
+                }
+
+                let name = sanitize_name(&name);
+                self.event(TransferEvent::Info(format!(
+                    "{} offers {} ({}), type /accept or /reject",
+                    participant.username,
+                    name,
+                    format_size(size)
+                )));
+
+                if let Ok(mut incoming) = self.incoming.lock() {
+                    incoming.insert(
+                        id.clone(),
+                        IncomingFile {
+                            from: participant.clone(),
+                            name: name,
+                            size: size,
+                            sha256: sha256,
+                            received: 0,
+                            accepted: false,
+                            active: false,
+                        },
+                    );
+                }
+                if let Ok(mut offers) = self.offers.lock() {
+                    offers.push_back(id);
+                }
+            }
+            TransferMessage::Accept { id, offset } => {
+                let offered = match self.outgoing.lock() {
+                    Ok(outgoing) => outgoing.get(&id).is_some_and(|outgoing_file| {
+                        offset < outgoing_file.size
+                            && outgoing_file.recipients.contains(participant)
+                    }),
+                    Err(_) => false,
+                };
+                if !offered {
+                    info!(
+                        "ignoring accept from {} for {} at {}, which wasn't offered to them",
+                        participant, id, offset
+                    );
+                    return;
+                }
+
+                let peer_connection = match weak_peer_connection.upgrade() {
+                    Some(peer_connection) => peer_connection,
+                    None => return,
+                };
+
+                tokio::spawn(send_file(
+                    self.clone(),
+                    peer_connection,
+                    participant.clone(),
+                    id,
+                    offset,
+                ));
+            }
+            TransferMessage::Reject { id } => {
+                let name = match self.outgoing.lock() {
+                    Ok(outgoing) => outgoing
+                        .get(&id)
+                        .map(|outgoing_file| outgoing_file.name.clone()),
+                    Err(_) => None,
+                };
+                // or the sender no longer has the file we wanted to resume
+                let name = name.or_else(|| self.reject(&id));
+
+                if let Some(name) = name {
+                    self.event(TransferEvent::Info(format!(
+                        "{} declined {}",
+                        participant.username, name
+                    )));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn receive_file(
+        self: &Arc<Self>,
+        participant: &Participant,
+        data_channel: Arc<RTCDataChannel>,
+    ) {
+        let id = data_channel
+            .label()
+            .trim_start_matches(FILE_CHANNEL_PREFIX)
+            .to_owned();
+
+        let (name, size, offset) = match self.incoming.lock() {
+            Ok(mut incoming) => match incoming.get_mut(&id) {
+                Some(incoming_file) if incoming_file.accepted => {
+                    incoming_file.active = true;
+                    (
+                        incoming_file.name.clone(),
+                        incoming_file.size,
+                        incoming_file.received,
+                    )
+                }
+                _ => {
+                    info!("ignoring file channel from {} for {}", participant, id);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+
+        let description = format!("↓ {} from {}", name, participant.username);
+        let file: Arc<tokio::sync::Mutex<Option<tokio::fs::File>>> =
+            Arc::new(tokio::sync::Mutex::new(None));
+
+        let transfers = self.clone();
+        let message_id = id.clone();
+        let message_name = name.clone();
+        let message_file = file.clone();
+        let message_data_channel = Arc::downgrade(&data_channel);
+        let mut last_progress = Instant::now();
+        data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let transfers = transfers.clone();
+            let id = message_id.clone();
+            let name = message_name.clone();
+            let file = message_file.clone();
+            let data_channel = message_data_channel.clone();
+            let description = description.clone();
+            let show_progress = last_progress.elapsed() >= PROGRESS_INTERVAL;
+            if show_progress {
+                last_progress = Instant::now();
+            }
+
+            Box::pin(async move {
+                let mut file = file.lock().await;
+                if file.is_none() {
+                    *file = match transfers.open_partial(&id, &name, offset).await {
+                        Ok(partial) => Some(partial),
+                        Err(e) => {
+                            transfers.fail(&id, format!("unable to save {}: {}", name, e));
+                            close(&data_channel).await;
+                            return;
+                        }
+                    };
+                }
+                let partial = match file.as_mut() {
+                    Some(partial) => partial,
+                    None => return,
+                };
+
+                if let Err(e) = partial.write_all(&message.data).await {
+                    transfers.fail(&id, format!("unable to save {}: {}", name, e));
+                    *file = None;
+                    close(&data_channel).await;
+                    return;
+                }
+
+                let received = transfers.record_received(&id, message.data.len() as u64);
+                if received < size {
+                    if show_progress {
+                        transfers.event(TransferEvent::Progress {
+                            key: id,
+                            description: description,
+                            done: received,
+                            size: size,
+                        });
+                    }
+                    return;
+                }
+
+                let _ = partial.flush().await;
+                *file = None;
+                transfers.finish(&id).await;
+                close(&data_channel).await;
+            })
+        }));
+
+        let transfers = self.clone();
+        let close_participant = participant.clone();
+        data_channel.on_close(Box::new(move || {
+            transfers.interrupt(&id, &close_participant);
+            Box::pin(async {})
+        }));
+    }
+
+    fn record_received(&self, id: &str, received: u64) -> u64 {
+        match self.incoming.lock() {
+            Ok(mut incoming) => match incoming.get_mut(id) {
+                Some(incoming_file) => {
+                    incoming_file.received += received;
+                    incoming_file.received
+                }
+                None => 0,
+            },
+            Err(_) => 0,
+        }
+    }
+
+    fn interrupt(&self, id: &str, participant: &Participant) {
+        let name = match self.incoming.lock() {
+            Ok(mut incoming) => match incoming.get_mut(id) {
+                Some(incoming_file) if incoming_file.received < incoming_file.size => {
+                    incoming_file.active = false;
+                    incoming_file.name.clone()
+                }
+                _ => return,
+            },
+            Err(_) => return,
+        };
+
+        self.event(TransferEvent::Ended { key: id.to_owned() });
+        self.event(TransferEvent::Info(format!(
+            "transfer of {} was interrupted, it resumes when {} reconnects",
+            name, participant.username
+        )));
+    }
+
+    fn fail(&self, id: &str, error: String) {
+        if let Ok(mut incoming) = self.incoming.lock() {
+            incoming.remove(id);
+        }
+        self.event(TransferEvent::Ended { key: id.to_owned() });
+        self.event(TransferEvent::Error(error));
+    }
+
+    // checks the hash and moves the file out of the way of other partial downloads
+    async fn finish(&self, id: &str) {
+        let incoming_file = match self.incoming.lock() {
+            Ok(mut incoming) => incoming.remove(id),
+            Err(_) => None,
+        };
+        let incoming_file = match incoming_file {
+            Some(incoming_file) => incoming_file,
+            None => return,
+        };
+        self.event(TransferEvent::Ended { key: id.to_owned() });
+
+        let partial = self.partial_path(id, &incoming_file.name);
+        match hash(&partial).await {
+            Ok(sha256) if sha256 == incoming_file.sha256 => {}
+            Ok(_) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                self.event(TransferEvent::Error(format!(
+                    "{} doesn't match the file that was offered, discarding it",
+                    incoming_file.name
+                )));
+                return;
+            }
+            Err(e) => {
+                self.event(TransferEvent::Error(format!(
+                    "unable to check {}: {}",
+                    incoming_file.name, e
+                )));
+                return;
+            }
+        }
+
+        let path = self.download_path(&incoming_file.name);
+        match tokio::fs::rename(&partial, &path).await {
+            Ok(()) => self.event(TransferEvent::Info(format!(
+                "saved {} to {}",
+                incoming_file.name,
+                path.display()
+            ))),
+            Err(e) => self.event(TransferEvent::Error(format!(
+                "unable to save {}: {}",
+                incoming_file.name, e
+            ))),
+        }
+    }
+}
+
+async fn close(data_channel: &Weak<RTCDataChannel>) {
+    if let Some(data_channel) = data_channel.upgrade() {
+        let _ = data_channel.close().await;
+    }
+}
+
+async fn send_file(
+    transfers: Arc<Transfers>,
+    peer_connection: Arc<RTCPeerConnection>,
+    participant: Participant,
+    id: String,
+    offset: u64,
+) {
+    let (path, name, size) = match transfers.outgoing.lock() {
+        Ok(outgoing) => match outgoing.get(&id) {
+            Some(outgoing_file) => (
+                outgoing_file.path.clone(),
+                outgoing_file.name.clone(),
+                outgoing_file.size,
+            ),
+            None => return,
+        },
+        Err(_) => return,
+    };
+
+    let context = TransferContext {
+        transfers: transfers.clone(),
+        key: format!("{} {}", id, participant),
+        id: id,
+        path: path,
+        size: size,
+        description: format!("↑ {} to {}", name, participant.username),
+    };
+    let result = stream_file(&context, &peer_connection, offset).await;
+
+    transfers.event(TransferEvent::Ended { key: context.key });
+    match result {
+        Ok(()) => transfers.event(TransferEvent::Info(format!(
+            "sent {} to {}",
+            name, participant.username
+        ))),
+        Err(e) => transfers.event(TransferEvent::Error(format!(
+            "sending {} to {} stopped: {}",
+            name, participant.username, e
+        ))),
+    }
+}
+
+async fn stream_file(
+    context: &TransferContext,
+    peer_connection: &RTCPeerConnection,
+    offset: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = RTCDataChannelInit {
+        ordered: Some(true),
+        ..Default::default()
+    };
+    let data_channel = peer_connection
+        .create_data_channel(
+            &format!("{}{}", FILE_CHANNEL_PREFIX, context.id),
+            Some(options),
+        )
+        .await?;
+
+    let opened = Arc::new(Notify::new());
+    let open_notify = opened.clone();
+    data_channel.on_open(Box::new(move || {
+        open_notify.notify_one();
+        Box::pin(async {})
+    }));
+    timeout(OPEN_TIMEOUT, opened.notified()).await?;
+
+    let closed = Arc::new(Notify::new());
+    let close_notify = closed.clone();
+    data_channel.on_close(Box::new(move || {
+        close_notify.notify_one();
+        Box::pin(async {})
+    }));
+
+    // wait for the peer to catch up instead of buffering the whole file
+    let buffered_amount_low = Arc::new(Notify::new());
+    let low_notify = buffered_amount_low.clone();
+    data_channel
+        .set_buffered_amount_low_threshold(BUFFERED_AMOUNT_LOW)
+        .await;
+    data_channel
+        .on_buffered_amount_low(Box::new(move || {
+            low_notify.notify_one();
+            Box::pin(async {})
+        }))
+        .await;
+
+    let mut file = tokio::fs::File::open(&context.path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+    let mut sent = offset;
+    let mut last_progress = Instant::now();
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    while sent < context.size {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Err("the file got shorter".into());
+        }
+
+        // a slow peer can take as long as it likes, as long as the channel stays open
+        while data_channel.buffered_amount().await > BUFFERED_AMOUNT_HIGH {
+            if data_channel.ready_state() != RTCDataChannelState::Open {
+                return Err("the channel closed".into());
+            }
+            tokio::select! {
+                _ = buffered_amount_low.notified() => {}
+                _ = closed.notified() => return Err("the channel closed".into()),
+            }
+        }
+        data_channel
+            .send(&Bytes::copy_from_slice(&chunk[..read]))
+            .await?;
+        sent += read as u64;
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            context.transfers.event(TransferEvent::Progress {
+                key: context.key.clone(),
+                description: context.description.clone(),
+                done: sent,
+                size: context.size,
+            });
+        }
+    }
+
+    // the peer closes the channel once it has everything, so a close before then means it's gone
+    while data_channel.buffered_amount().await > 0 {
+        if data_channel.ready_state() != RTCDataChannelState::Open {
+            return Err("the channel closed".into());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(DRAIN_INTERVAL) => {}
+            _ = closed.notified() => return Err("the channel closed".into()),
+        }
+    }
+    Ok(())
+}
+
+// Progress and results of transfers are handed to the UI as they happen
+pub async fn init_transfers(
+    client: &Arc<tokio::sync::Mutex<Client>>,
+) -> UnboundedReceiver<TransferEvent> {
+    let (sender, receiver) = unbounded_channel();
+    client.lock().await.transfers = Some(Arc::new(Transfers::new(sender)));
+    receiver
+}
+
+impl Client {
+    // offers the file to everyone we have a data channel to, who can then accept it
+    pub(crate) async fn offer_file(&mut self, path: &str) -> Result<String, Error> {
+        let transfers = match &self.transfers {
+            Some(transfers) => transfers.clone(),
+            None => return Err(Error::NoDataChannel),
+        };
+
+        let read_error = || Error::ReadFile {
+            path: path.to_owned(),
+        };
+        let path_buf = PathBuf::from(path);
+        let metadata = tokio::fs::metadata(&path_buf)
+            .await
+            .map_err(|_| read_error())?;
+        if !metadata.is_file() {
+            return Err(read_error());
+        }
+        if metadata.len() == 0 {
+            return Err(Error::EmptyFile {
+                path: path.to_owned(),
+            });
+        }
+        let name = path_buf
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(String::from)
+            .ok_or_else(read_error)?;
+        let sha256 = hash(&path_buf).await.map_err(|_| read_error())?;
+
+        self.sent_files += 1;
+        let id = format!("{}-{}", self.session, self.sent_files);
+        let recipients = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| {
+                peer.chat_channel().is_some_and(|chat_channel| {
+                    chat_channel.ready_state() == RTCDataChannelState::Open
+                })
+            })
+            .map(|(participant, _)| participant.clone())
+            .collect();
+        transfers.add_outgoing(&id, path_buf, &name, metadata.len(), recipients);
+
+        let message = ChannelMessage::Transfer(TransferMessage::Offer {
+            id: id,
+            name: name.clone(),
+            size: metadata.len(),
+            sha256: sha256,
+        });
+        let json_string = match serde_json::to_string(&message) {
+            Ok(json_string) => json_string,
+            Err(_) => return Err(Error::Serialization),
+        };
+        if !self.send_to_peers(&json_string).await
+            && self
+                .peers
+                .values()
+                .all(|peer| peer.chat_channel().is_none())
+        {
+            return Err(Error::NoDataChannel);
+        }
+
+        Ok(format!(
+            "offered {} ({})",
+            name,
+            format_size(metadata.len())
+        ))
+    }
+
+    pub(crate) async fn accept_file(&mut self) -> Result<(), Error> {
+        let transfers = self.transfers.clone().ok_or(Error::NoFileOffer)?;
+        let (id, from) = transfers.next_offer().ok_or(Error::NoFileOffer)?;
+        let offset = transfers.accept(&id).ok_or(Error::NoFileOffer)?;
+
+        let message = ChannelMessage::Transfer(TransferMessage::Accept {
+            id: id,
+            offset: offset,
+        });
+        self.send_to_peer(&from, &message).await
+    }
+
+    pub(crate) async fn reject_file(&mut self) -> Result<Option<String>, Error> {
+        let transfers = self.transfers.clone().ok_or(Error::NoFileOffer)?;
+        let (id, from) = transfers.next_offer().ok_or(Error::NoFileOffer)?;
+        let name = transfers.reject(&id);
+
+        let message = ChannelMessage::Transfer(TransferMessage::Reject { id: id });
+        self.send_to_peer(&from, &message).await?;
+        Ok(name.map(|name| format!("declined {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "a1b2-1";
+    const NAME: &str = "notes.txt";
+
+    fn transfers(test: &str) -> (Arc<Transfers>, UnboundedReceiver<TransferEvent>) {
+        let download_dir =
+            env::temp_dir().join(format!("transfer-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&download_dir);
+
+        let (sender, receiver) = unbounded_channel();
+        let mut transfers = Transfers::new(sender);
+        transfers.download_dir = download_dir;
+        (Arc::new(transfers), receiver)
+    }
+
+    fn contents() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn add_incoming(transfers: &Transfers, sha256: String, received: u64) {
+        transfers.incoming.lock().unwrap().insert(
+            ID.to_owned(),
+            IncomingFile {
+                from: Participant {
+                    username: String::from("alice"),
+                    session: String::from("a1b2"),
+                },
+                name: NAME.to_owned(),
+                size: contents().len() as u64,
+                sha256: sha256,
+                received: received,
+                accepted: true,
+                active: true,
+            },
+        );
+    }
+
+    async fn receive(transfers: &Transfers, offset: usize) {
+        let contents = contents();
+        let mut partial = transfers
+            .open_partial(ID, NAME, offset as u64)
+            .await
+            .unwrap();
+        partial.write_all(&contents[offset..]).await.unwrap();
+        partial.flush().await.unwrap();
+        transfers.record_received(ID, (contents.len() - offset) as u64);
+        transfers.finish(ID).await;
+    }
+
+    fn errors(events: &mut UnboundedReceiver<TransferEvent>) -> Vec<String> {
+        let mut errors = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let TransferEvent::Error(error) = event {
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn ids() {
+        assert!(valid_id("a1b2-1"));
+        assert!(valid_id("ABC-def-42"));
+        assert!(!valid_id(""));
+        assert!(!valid_id("../../.ssh/authorized_keys"));
+        assert!(!valid_id("a/b"));
+        assert!(!valid_id("a.b"));
+        assert!(!valid_id("a b"));
+    }
+
+    #[tokio::test]
+    async fn resumes_from_partial_file() {
+        let (transfers, mut events) = transfers("resume");
+        let contents = contents();
+        let offset = 40_000;
+
+        // what arrived before the interruption, followed by a chunk that was cut off
+        std::fs::create_dir_all(&transfers.download_dir).unwrap();
+        let mut partial = contents[..offset].to_vec();
+        partial.extend_from_slice(&[0xff; 1000]);
+        std::fs::write(transfers.partial_path(ID, NAME), partial).unwrap();
+
+        let sha256 = format!("{:x}", Sha256::digest(&contents));
+        add_incoming(&transfers, sha256, offset as u64);
+        receive(&transfers, offset).await;
+
+        assert!(errors(&mut events).is_empty());
+        assert_eq!(
+            std::fs::read(transfers.download_dir.join(NAME)).unwrap(),
+            contents
+        );
+        assert!(!transfers.partial_path(ID, NAME).exists());
+        std::fs::remove_dir_all(&transfers.download_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_hash_mismatch() {
+        let (transfers, mut events) = transfers("mismatch");
+        add_incoming(&transfers, "0".repeat(64), 0);
+        receive(&transfers, 0).await;
+
+        assert_eq!(errors(&mut events).len(), 1);
+        assert!(!transfers.download_dir.join(NAME).exists());
+        assert!(!transfers.partial_path(ID, NAME).exists());
+        std::fs::remove_dir_all(&transfers.download_dir).unwrap();
+    }
+}
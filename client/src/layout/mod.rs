@@ -1,9 +1,6 @@
 use crossterm::{QueueableCommand, cursor::MoveTo, style::Print};
 
-use crate::{
-    chat::Chatbox,
-    video::{LocalVideoPanel, PeerVideoPanel},
-};
+use crate::{chat::Chatbox, stats::StatsPanel, video::LocalVideoPanel};
 
 pub trait Drawable {
     const TOP_LEFT_CORNER: &str = "╭";
@@ -20,11 +17,19 @@ pub trait Drawable {
     fn width(&self) -> u16;
     fn height(&self) -> u16;
 
+    fn title(&self) -> Option<String> {
+        None
+    }
+
     fn draw_border(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
         let x = self.x();
         let y = self.y();
         let w = self.width();
         let h = self.height();
+        // too small to have any inside
+        if w < 2 || h < 2 {
+            return Ok(());
+        }
 
         stdout
             .queue(MoveTo(x, y))?
@@ -54,12 +59,71 @@ pub trait Drawable {
                 .queue(Print(Self::VERTICAL_BORDER))?;
         }
 
+        if let Some(title) = self.title() {
+            let title = format!(" {} ", title);
+            let max_width = w.saturating_sub(4) as usize;
+            let title = title.chars().take(max_width).collect::<String>();
+            stdout.queue(MoveTo(x + 2, y))?.queue(Print(title))?;
+        }
+
+        Ok(())
+    }
+}
+
+// The area to the left of the chatbox, shared by the local panel and one panel per peer
+pub struct VideoGrid {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    // viewers have no local panel, so the first peer gets the whole grid
+    viewer: bool,
+}
+
+impl VideoGrid {
+    // keeps the local panel at half height while there's nobody else in the call
+    const MIN_PANELS: usize = 2;
+
+    pub fn viewer(&self) -> bool {
+        self.viewer
+    }
+
+    // (x, y, width, height) of each panel, filled row by row
+    pub fn cells(&self, panels: usize) -> Vec<(u16, u16, u16, u16)> {
+        let min_panels = if self.viewer { 1 } else { Self::MIN_PANELS };
+        let panels = panels.max(min_panels);
+        let columns = if panels <= Self::MIN_PANELS { 1 } else { 2 };
+        let rows = panels.div_ceil(columns);
+        let width = self.width / columns as u16;
+        let height = self.height / rows as u16;
+
+        (0..panels)
+            .map(|i| {
+                let column = (i % columns) as u16;
+                let row = (i / columns) as u16;
+                (
+                    self.x + column * width,
+                    self.y + row * height,
+                    width,
+                    height,
+                )
+            })
+            .collect()
+    }
+
+    pub fn clear(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        for i in 0..self.height {
+            stdout
+                .queue(MoveTo(self.x, self.y + i))?
+                .queue(Print(" ".repeat(self.width as usize)))?;
+        }
         Ok(())
     }
 }
 
-pub fn create_layout()
--> Result<(Chatbox, LocalVideoPanel, PeerVideoPanel), Box<dyn std::error::Error>> {
+pub fn create_layout(
+    viewer: bool,
+) -> Result<(Chatbox, StatsPanel, LocalVideoPanel, VideoGrid), Box<dyn std::error::Error>> {
     let size = match termsize::get() {
         Some(size) => size,
         None => panic!("Unable to get terminal size."),
@@ -70,11 +134,29 @@ pub fn create_layout()
     let width = size.cols;
     let height = size.rows;
     let chatbox_height = height - (height % 2) - 2;
-    let video_panel_height = chatbox_height / 2;
+
+    // without a local panel, the chatbox gets some of the room it would have taken
+    let video_width = if viewer { 96 } else { 128 };
+    let chatbox = Chatbox::new(video_width + 2, 1, width - video_width - 4, chatbox_height);
+    let stats_panel = StatsPanel::new(
+        chatbox.x() + 2,
+        chatbox.y() + 1,
+        chatbox.width().saturating_sub(4),
+    );
+
+    let video_grid = VideoGrid {
+        x: 1,
+        y: 1,
+        width: video_width,
+        height: chatbox_height,
+        viewer: viewer,
+    };
+    let (x, y, width, height) = video_grid.cells(1)[0];
 
     Ok((
-        Chatbox::new(130, 1, width - 128 - 4, chatbox_height),
-        LocalVideoPanel::new_local(1, 1, 128, video_panel_height)?,
-        PeerVideoPanel::new_peer(1, 1 + video_panel_height, 128, video_panel_height)?,
+        chatbox,
+        stats_panel,
+        LocalVideoPanel::new_local(x, y, width, height)?,
+        video_grid,
     ))
 }
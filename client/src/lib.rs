@@ -1,5 +1,8 @@
+pub mod board;
 pub mod chat;
 pub mod client;
 pub mod layout;
 pub mod logging;
+pub mod stats;
+pub mod terminal;
 pub mod video;
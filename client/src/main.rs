@@ -1,15 +1,44 @@
-use std::{io::stdout, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    future::poll_fn,
+    io::{Stdout, stdout},
+    sync::Arc,
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 use client::{
+    board::Whiteboard,
     chat::command::Parser,
-    client::{Client, signaling::init_peer_connection},
-    layout::{Drawable, create_layout},
+    client::{
+        Client,
+        board::{BoardEvent, init_board},
+        data_channel::{ChatPath, init_chat},
+        message::{Message, Participant, Renderer, RoomMessage, SignalMessage},
+        signaling::init_signaling,
+        terminal::{TerminalEvent, TerminalOwner, init_terminal},
+        transfer::{TransferEvent, init_transfers},
+    },
+    layout::{Drawable, VideoGrid, create_layout},
     logging::init_logging,
+    stats::{FrameCounter, StatsCollector},
+    video::{
+        LocalVideoPanel, PeerVideoPanel, VideoPanel,
+        channel::FrameReceiver,
+        handler::{PeerFrame, VideoHandler},
+        jitter::ReceiveStats,
+        latency::{LatencyBreakdown, LatencyMeter},
+        pool::SharedFrame,
+    },
 };
 use crossterm::{
     ExecutableCommand, QueueableCommand,
     cursor::MoveTo,
-    event::{Event, EventStream},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent,
+        KeyModifiers,
+    },
     terminal::{self, Clear, ClearType},
 };
 use dotenv::dotenv;
@@ -18,6 +47,125 @@ use futures::{FutureExt, StreamExt};
 use client::chat::command::ChatboxCommand;
 use client::chat::command::ChatboxInput;
 use tokio::sync::Mutex;
+use tracing::info;
+use webrtc::{
+    ice_transport::ice_gathering_state::RTCIceGatheringState,
+    peer_connection::peer_connection_state::RTCPeerConnectionState,
+};
+
+const VIEWER_FLAG: &str = "--viewer";
+
+struct RemotePeer {
+    panel: PeerVideoPanel,
+    // None once the peer stops sending, until a new track arrives
+    frames: Option<FrameReceiver<PeerFrame>>,
+    dropped: u64,
+    latency_meter: LatencyMeter,
+    // the peer lets us type into the terminal it shares
+    control: bool,
+}
+
+impl RemotePeer {
+    fn new(
+        participant: &Participant,
+        frames: Option<FrameReceiver<PeerFrame>>,
+        raised_hands: &BTreeSet<Participant>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut panel = PeerVideoPanel::new_peer(0, 0, 4, 4)?;
+        panel.set_title(Some(participant.username.clone()));
+        // the hand may have gone up before there was a panel to show it on
+        panel.set_hand_raised(
+            raised_hands
+                .iter()
+                .any(|raised| raised.matches(participant)),
+        );
+        Ok(Self {
+            panel: panel,
+            frames: frames,
+            dropped: 0,
+            latency_meter: LatencyMeter::new(),
+            control: false,
+        })
+    }
+}
+
+// Feeds a terminal event to the panel that shows the terminal
+fn show_terminal<T: VideoHandler>(
+    stdout: &mut Stdout,
+    panel: &mut VideoPanel<T>,
+    terminal_event: &TerminalEvent,
+) -> Result<(), std::io::Error> {
+    match terminal_event {
+        TerminalEvent::Output { data, .. } => panel.terminal_mut().process(data),
+        TerminalEvent::Resize { columns, rows, .. } => panel.terminal_mut().resize(*columns, *rows),
+        TerminalEvent::Ended { .. } => panel.clear_terminal(),
+        TerminalEvent::Control { .. } => return Ok(()),
+    }
+    panel.draw(stdout)
+}
+
+// Ctrl+] leaves a terminal, like it does in telnet. Some terminals report it as Ctrl+5
+fn is_terminal_escape(key_event: &KeyEvent) -> bool {
+    key_event.modifiers.contains(KeyModifiers::CONTROL)
+        && matches!(key_event.code, KeyCode::Char(']') | KeyCode::Char('5'))
+}
+
+// Frames from the webcam, which viewers never open
+async fn next_local_frame(
+    receiver: &mut Option<FrameReceiver<SharedFrame>>,
+) -> Option<SharedFrame> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Lays the local panel, the board while it's open and one panel per peer out over the grid
+// again, returning the viewport that peers should encode for
+fn reflow(
+    stdout: &mut Stdout,
+    video_grid: &VideoGrid,
+    local_video_panel: &mut LocalVideoPanel,
+    whiteboard: &mut Whiteboard,
+    remote_peers: &mut BTreeMap<Participant, RemotePeer>,
+) -> Result<Option<(usize, usize, Renderer)>, std::io::Error> {
+    video_grid.clear(stdout)?;
+
+    let local_cells = if video_grid.viewer() { 0 } else { 1 };
+    let board_cells = if whiteboard.visible() { 1 } else { 0 };
+    let mut cells = video_grid.cells(local_cells + board_cells + remote_peers.len());
+    if whiteboard.visible() {
+        let (x, y, width, height) = cells.remove(local_cells);
+        whiteboard.resize(x, y, width, height);
+        whiteboard.draw_border(stdout)?;
+        whiteboard.draw(stdout)?;
+    }
+
+    if !video_grid.viewer() {
+        let (x, y, width, height) = cells[0];
+        local_video_panel.resize(x, y, width, height);
+        local_video_panel.draw_border(stdout)?;
+        // video is drawn again with the next frame, but terminals only change with their output
+        if local_video_panel.terminal().is_some() {
+            local_video_panel.draw(stdout)?;
+        }
+    }
+
+    for (remote_peer, &(x, y, width, height)) in
+        remote_peers.values_mut().zip(&cells[local_cells..])
+    {
+        remote_peer.panel.resize(x, y, width, height);
+        remote_peer.panel.draw_border(stdout)?;
+        if remote_peer.panel.terminal().is_some() {
+            remote_peer.panel.draw(stdout)?;
+        }
+    }
+
+    Ok(remote_peers
+        .values()
+        .next()
+        .map(|remote_peer| remote_peer.panel.viewport()))
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -31,25 +179,71 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .queue(crossterm::cursor::Hide)?;
     terminal::enable_raw_mode()?;
 
-    let (mut chatbox, mut local_video_panel, mut peer_video_panel) = create_layout()?;
+    let viewer = env::args().any(|arg| arg == VIEWER_FLAG);
+    let (mut chatbox, mut stats_panel, mut local_video_panel, video_grid) = create_layout(viewer)?;
     chatbox.draw_border(&mut stdout)?;
     chatbox.draw(&mut stdout)?;
-    local_video_panel.draw_border(&mut stdout)?;
-    local_video_panel.draw(&mut stdout)?;
-    peer_video_panel.draw_border(&mut stdout)?;
-    peer_video_panel.draw(&mut stdout)?;
+    if !viewer {
+        local_video_panel.draw_border(&mut stdout)?;
+        local_video_panel.draw(&mut stdout)?;
+    }
 
     let mut input_stream = EventStream::new();
 
     let mut client = Client::new();
-    let mut local_video_receiver = client.start_webcam().await;
+    client.set_viewer(viewer);
+    // where the first peer's panel goes
+    let (x, y, width, height) = if viewer {
+        video_grid.cells(1)[0]
+    } else {
+        video_grid.cells(2)[1]
+    };
+    let (width, height, renderer) = PeerVideoPanel::new_peer(x, y, width, height)?.viewport();
+    client.set_viewport(width, height, renderer);
+    let mut local_video_receiver = if viewer {
+        None
+    } else {
+        Some(client.start_webcam().await)
+    };
+    if viewer {
+        chatbox.log("watching as a viewer, without the webcam");
+        chatbox.draw(&mut stdout)?;
+    }
+
+    let mut whiteboard = Whiteboard::new(client.session());
+    // tells our own reactions and hand apart from everyone else's when the server echoes them
+    let session = client.session().to_owned();
 
     let client = Arc::new(Mutex::new(client));
-    let mut peer_video_receiver = init_peer_connection(&client).await?;
+    let mut peer_tracks = init_signaling(&client).await;
+    let mut peer_chats = init_chat(&client).await;
+    let mut transfer_events = init_transfers(&client).await;
+    let mut terminal_events = init_terminal(&client).await;
+    let mut board_events = init_board(&client).await;
+    // only mentioned once, until the board is opened
+    let mut board_announced = false;
+    let mut raised_hands: BTreeSet<Participant> = BTreeSet::new();
+    // keys go to this terminal instead of the chatbox
+    let mut terminal_focus: Option<TerminalOwner> = None;
+    let mut remote_peers: BTreeMap<Participant, RemotePeer> = BTreeMap::new();
+    let mut status_interval = tokio::time::interval(Duration::from_secs(1));
+    let mut dropped_frames = (0, 0, 0, 0);
+    let mut stats_collector = StatsCollector::new();
+    let rendered_frames = FrameCounter::default();
 
     loop {
         let mut client_guard = client.lock().await;
         let poll_message_future = client_guard.poll_message();
+        let peer_frame_future = poll_fn(|cx| {
+            for (participant, remote_peer) in remote_peers.iter_mut() {
+                if let Some(frames) = remote_peer.frames.as_mut()
+                    && let Poll::Ready(frame) = frames.poll_recv(cx)
+                {
+                    return Poll::Ready((participant.clone(), frame));
+                }
+            }
+            Poll::Pending
+        });
 
         tokio::select! {
             Some(message) = poll_message_future => {
@@ -72,7 +266,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     },
                 }
 
-                chatbox.receive_message(&message);
+                if let Message::Room { room_message: RoomMessage::Leave { username, session } } = &message {
+                    let participant = Participant {
+                        username: username.clone(),
+                        session: session.clone(),
+                    };
+                    raised_hands.retain(|raised| !raised.matches(&participant));
+                    chatbox.set_raised_hands(raised_hands.iter().map(|raised| raised.username.clone()).collect());
+                    let remote_peer_count = remote_peers.len();
+                    remote_peers.retain(|key, _| !key.matches(&participant));
+                    if remote_peers.len() != remote_peer_count {
+                        reflow(&mut stdout, &video_grid, &mut local_video_panel, &mut whiteboard, &mut remote_peers)?;
+                    }
+                }
+                if let Message::Room { room_message: RoomMessage::Reaction { username, session: from, content } } = &message {
+                    let participant = Participant {
+                        username: username.clone(),
+                        session: from.clone(),
+                    };
+                    if *from == session && !viewer {
+                        local_video_panel.set_reaction(content);
+                        local_video_panel.draw(&mut stdout)?;
+                    } else {
+                        let mut shown = false;
+                        for (_, remote_peer) in remote_peers.iter_mut().filter(|(key, _)| key.matches(&participant)) {
+                            remote_peer.panel.set_reaction(content);
+                            remote_peer.panel.draw(&mut stdout)?;
+                            shown = true;
+                        }
+                        // nowhere to put it over, e.g. because they aren't broadcasting
+                        if !shown {
+                            chatbox.log(&format!("{} reacted {}", username, content));
+                        }
+                    }
+                }
+                if let Message::Room { room_message: RoomMessage::Hand { username, session: from, raised } } = &message {
+                    let participant = Participant {
+                        username: username.clone(),
+                        session: from.clone(),
+                    };
+                    raised_hands.retain(|raised| !raised.matches(&participant));
+                    if *raised {
+                        raised_hands.insert(participant.clone());
+                    }
+                    chatbox.set_raised_hands(raised_hands.iter().map(|raised| raised.username.clone()).collect());
+
+                    if *from == session && !viewer {
+                        local_video_panel.set_hand_raised(*raised);
+                        local_video_panel.draw_border(&mut stdout)?;
+                    }
+                    for (_, remote_peer) in remote_peers.iter_mut().filter(|(key, _)| key.matches(&participant)) {
+                        remote_peer.panel.set_hand_raised(*raised);
+                        remote_peer.panel.draw_border(&mut stdout)?;
+                    }
+                }
+                if let Message::Signal { from: Some(from), signal_message: SignalMessage::Stopped, .. } = &message {
+                    for (_, remote_peer) in remote_peers.iter_mut().filter(|(key, _)| key.matches(from)) {
+                        remote_peer.frames = None;
+                        remote_peer.panel.set_stopped(true);
+                        remote_peer.panel.draw(&mut stdout)?;
+                    }
+                }
+                let accepted = match &message {
+                    Message::Room { room_message } => client_mut.accept_chat(room_message),
+                    _ => true,
+                };
+                drop(client_mut);
+
+                if accepted {
+                    chatbox.receive_message(&message);
+                }
                 chatbox.draw(&mut stdout)?;
             }
 
@@ -81,9 +344,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 let key_event = match input {
                     Ok(Event::Key(key_event)) => key_event,
+                    Ok(Event::Mouse(mouse_event)) if whiteboard.focused() => {
+                        let cells = whiteboard.mouse(&mouse_event);
+                        whiteboard.draw(&mut stdout)?;
+                        if !cells.is_empty() {
+                            client.lock().await.send_board(cells).await;
+                        }
+                        continue;
+                    },
                     _ => continue,
                 };
 
+                if whiteboard.focused() {
+                    if key_event.code == KeyCode::Esc || is_terminal_escape(&key_event) {
+                        whiteboard.set_focused(false);
+                        stdout.execute(DisableMouseCapture)?;
+                        chatbox.log("back to chat");
+                        chatbox.draw(&mut stdout)?;
+                    } else {
+                        let cells = whiteboard.input(&key_event);
+                        if !cells.is_empty() {
+                            client.lock().await.send_board(cells).await;
+                        }
+                    }
+                    whiteboard.draw_border(&mut stdout)?;
+                    whiteboard.draw(&mut stdout)?;
+                    continue;
+                }
+
+                if let Some(owner) = &terminal_focus {
+                    if is_terminal_escape(&key_event) {
+                        terminal_focus = None;
+                        chatbox.log("back to chat");
+                        chatbox.draw(&mut stdout)?;
+                        continue;
+                    }
+
+                    let terminal = match owner {
+                        TerminalOwner::Local => local_video_panel.terminal(),
+                        TerminalOwner::Peer(participant) => remote_peers
+                            .get(participant)
+                            .and_then(|remote_peer| remote_peer.panel.terminal()),
+                    };
+                    let data = match terminal.and_then(|terminal| terminal.key_bytes(&key_event)) {
+                        Some(data) => data,
+                        None => continue,
+                    };
+                    if let Err(e) = client.lock().await.send_terminal_input(owner, data).await {
+                        terminal_focus = None;
+                        chatbox.error(&e.to_string());
+                        chatbox.draw(&mut stdout)?;
+                    }
+                    continue;
+                }
+
                 let input = match chatbox.input(&key_event) {
                     Ok(input) => input,
                     Err(e) => {
@@ -96,6 +410,71 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 match &input {
                     ChatboxInput::Command(command) => match command {
                         ChatboxCommand::Exit => break,
+                        ChatboxCommand::Stats => {
+                            stats_panel.toggle();
+                            chatbox.set_reserved_rows(stats_panel.reserved_rows());
+                            stats_panel.draw(&mut stdout)?;
+                        },
+                        ChatboxCommand::Debug => chatbox.toggle_debug(),
+                        ChatboxCommand::Board | ChatboxCommand::CloseBoard => {
+                            let visible = matches!(command, ChatboxCommand::Board);
+                            if visible != whiteboard.visible() {
+                                whiteboard.set_visible(visible);
+                                let viewport = reflow(
+                                    &mut stdout,
+                                    &video_grid,
+                                    &mut local_video_panel,
+                                    &mut whiteboard,
+                                    &mut remote_peers,
+                                )?;
+                                if let Some((width, height, renderer)) = viewport
+                                    && let Err(e) = client.lock().await.update_viewport(width, height, renderer).await
+                                {
+                                    chatbox.error(&e.to_string());
+                                }
+                            }
+
+                            if visible {
+                                board_announced = false;
+                                terminal_focus = None;
+                                whiteboard.set_focused(true);
+                                stdout.execute(EnableMouseCapture)?;
+                                whiteboard.draw_border(&mut stdout)?;
+                                whiteboard.draw(&mut stdout)?;
+                                chatbox.log(
+                                    "the mouse or Shift+arrows draw, right click erases, Tab changes the brush and Esc returns to chat",
+                                );
+                            }
+                        },
+                        ChatboxCommand::SaveBoard { path } => {
+                            match tokio::fs::write(path, whiteboard.to_text()).await {
+                                Ok(()) => chatbox.log(&format!("saved the board to {}", path)),
+                                Err(e) => chatbox.error(&format!("unable to save the board: {}", e)),
+                            }
+                        },
+                        ChatboxCommand::Focus => {
+                            // our own terminal first, then the first one we were let into
+                            terminal_focus = if local_video_panel.terminal().is_some() {
+                                Some(TerminalOwner::Local)
+                            } else {
+                                remote_peers
+                                    .iter()
+                                    .find(|(_, remote_peer)| {
+                                        remote_peer.control && remote_peer.panel.terminal().is_some()
+                                    })
+                                    .map(|(participant, _)| TerminalOwner::Peer(participant.clone()))
+                            };
+                            match &terminal_focus {
+                                Some(TerminalOwner::Local) => {
+                                    chatbox.log("typing into your terminal, Ctrl+] returns to chat")
+                                },
+                                Some(TerminalOwner::Peer(participant)) => chatbox.log(&format!(
+                                    "typing into {}'s terminal, Ctrl+] returns to chat",
+                                    participant.username
+                                )),
+                                None => chatbox.error("there's no terminal to type into"),
+                            }
+                        },
                         _ => {},
                     },
                     ChatboxInput::Exit => break,
@@ -120,10 +499,132 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 chatbox.draw(&mut stdout)?;
             },
 
-            Some(stream) = local_video_receiver.recv() => {
+            Some(room_message) = peer_chats.recv() => {
+                drop(client_guard);
+
+                if client.lock().await.accept_chat(&room_message) {
+                    chatbox.receive_room_message(&room_message, ChatPath::DataChannel);
+                    chatbox.draw(&mut stdout)?;
+                }
+            },
+
+            Some(transfer_event) = transfer_events.recv() => {
                 drop(client_guard);
 
-                match local_video_panel.receive_stream(&stream) {
+                match transfer_event {
+                    TransferEvent::Info(content) => chatbox.log(&content),
+                    TransferEvent::Error(content) => chatbox.error(&content),
+                    TransferEvent::Progress { key, description, done, size } => {
+                        chatbox.set_progress(&key, &description, done, size)
+                    },
+                    TransferEvent::Ended { key } => chatbox.clear_progress(&key),
+                }
+                chatbox.draw(&mut stdout)?;
+            },
+
+            Some(terminal_event) = terminal_events.recv() => {
+                drop(client_guard);
+
+                let owner = match &terminal_event {
+                    TerminalEvent::Output { owner, .. }
+                    | TerminalEvent::Resize { owner, .. }
+                    | TerminalEvent::Ended { owner } => owner.clone(),
+                    TerminalEvent::Control { from, .. } => TerminalOwner::Peer(from.clone()),
+                };
+
+                // a peer can share a terminal without broadcasting
+                if let TerminalOwner::Peer(participant) = &owner
+                    && !remote_peers.contains_key(participant)
+                    && matches!(terminal_event, TerminalEvent::Output { .. } | TerminalEvent::Resize { .. })
+                {
+                    remote_peers.insert(participant.clone(), RemotePeer::new(participant, None, &raised_hands)?);
+                    let viewport =
+                        reflow(&mut stdout, &video_grid, &mut local_video_panel, &mut whiteboard, &mut remote_peers)?;
+                    if let Some((width, height, renderer)) = viewport
+                        && let Err(e) = client.lock().await.update_viewport(width, height, renderer).await
+                    {
+                        chatbox.error(&e.to_string());
+                        chatbox.draw(&mut stdout)?;
+                    }
+                }
+
+                match &owner {
+                    TerminalOwner::Local => {
+                        show_terminal(&mut stdout, &mut local_video_panel, &terminal_event)?;
+                        if let TerminalEvent::Ended { .. } = &terminal_event
+                            && client.lock().await.terminal_ended()
+                        {
+                            chatbox.log("the shared terminal exited");
+                        }
+                    },
+                    TerminalOwner::Peer(participant) => {
+                        let remote_peer = match remote_peers.get_mut(participant) {
+                            Some(remote_peer) => remote_peer,
+                            None => continue,
+                        };
+                        match &terminal_event {
+                            TerminalEvent::Control { granted: true, .. } => {
+                                remote_peer.control = true;
+                                chatbox.log(&format!(
+                                    "{} lets you type into their terminal, /focus to start",
+                                    participant.username
+                                ));
+                            },
+                            TerminalEvent::Control { granted: false, .. } => {
+                                remote_peer.control = false;
+                                chatbox.log(&format!("{} took back their terminal", participant.username));
+                            },
+                            TerminalEvent::Ended { .. } => {
+                                remote_peer.control = false;
+                                if remote_peer.frames.is_none() {
+                                    remote_peer.panel.set_stopped(true);
+                                }
+                                chatbox.log(&format!("{} stopped sharing their terminal", participant.username));
+                            },
+                            _ => {},
+                        }
+                        show_terminal(&mut stdout, &mut remote_peer.panel, &terminal_event)?;
+                    },
+                }
+
+                if matches!(terminal_event, TerminalEvent::Ended { .. } | TerminalEvent::Control { granted: false, .. })
+                    && terminal_focus.as_ref() == Some(&owner)
+                {
+                    terminal_focus = None;
+                }
+                chatbox.draw(&mut stdout)?;
+            },
+
+            Some(board_event) = board_events.recv() => {
+                drop(client_guard);
+
+                match board_event {
+                    BoardEvent::Edit(cells) => {
+                        if !whiteboard.merge(cells) {
+                            continue;
+                        }
+                        if whiteboard.visible() {
+                            whiteboard.draw(&mut stdout)?;
+                        } else if !board_announced {
+                            board_announced = true;
+                            chatbox.log("someone is drawing on the board, /board opens it");
+                            chatbox.draw(&mut stdout)?;
+                        }
+                    },
+                    BoardEvent::Opened(participant) => {
+                        let cells = whiteboard.cells();
+                        if !cells.is_empty() {
+                            client.lock().await.send_board_to(&participant, cells).await;
+                        }
+                    },
+                }
+            },
+
+            Some(stream) = next_local_frame(&mut local_video_receiver) => {
+                drop(client_guard);
+
+                match local_video_panel.receive_stream(stream) {
+                    Ok(_) if local_video_panel.terminal().is_some() => {},
                     Ok(_) => {
                         local_video_panel.draw(&mut stdout)?;
                     },
@@ -135,24 +636,221 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             },
 
-            Some(stream) = peer_video_receiver.recv() => {
+            Some(peer_track) = peer_tracks.recv() => {
+                drop(client_guard);
+
+                // the peer started broadcasting again, so keep its panel where it is
+                if let Some(remote_peer) = remote_peers.get_mut(&peer_track.participant) {
+                    remote_peer.frames = Some(peer_track.frames);
+                    remote_peer.dropped = 0;
+                    remote_peer.panel.set_stopped(false);
+                    continue;
+                }
+
+                let remote_peer = RemotePeer::new(&peer_track.participant, Some(peer_track.frames), &raised_hands)?;
+                remote_peers.insert(peer_track.participant, remote_peer);
+
+                let viewport =
+                    reflow(&mut stdout, &video_grid, &mut local_video_panel, &mut whiteboard, &mut remote_peers)?;
+                if let Some((width, height, renderer)) = viewport
+                    && let Err(e) = client.lock().await.update_viewport(width, height, renderer).await
+                {
+                    chatbox.error(&e.to_string());
+                    chatbox.draw(&mut stdout)?;
+                }
+            },
+
+            (participant, frame) = peer_frame_future => {
                 drop(client_guard);
 
-                match peer_video_panel.receive_stream(&stream) {
+                let remote_peer = match remote_peers.get_mut(&participant) {
+                    Some(remote_peer) => remote_peer,
+                    None => continue,
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => {
+                        // the track ended, e.g. because the peer connection was closed; the
+                        // panel stays until the peer leaves
+                        remote_peer.frames = None;
+                        remote_peer.panel.set_stopped(true);
+                        remote_peer.panel.draw(&mut stdout)?;
+                        continue;
+                    },
+                };
+                let peer_video_panel = &mut remote_peer.panel;
+
+                // later frames reference the ones that were skipped, so wait for a keyframe
+                let dropped = remote_peer.frames.as_ref().map_or(0, |frames| frames.dropped());
+                if dropped != remote_peer.dropped {
+                    remote_peer.dropped = dropped;
+                    let _ = peer_video_panel.receive_stream(Vec::new());
+                }
+
+                let decode_started = Instant::now();
+                match peer_video_panel.receive_stream(frame.data) {
                     Ok(_) => {
+                        let decode = decode_started.elapsed();
+                        peer_video_panel.set_recovering(false);
+                        // the decoder keeps up, but the terminal stays on top
+                        if peer_video_panel.terminal().is_some() {
+                            continue;
+                        }
+
+                        let render_started = Instant::now();
                         peer_video_panel.draw(&mut stdout)?;
+                        let render = render_started.elapsed();
+                        rendered_frames.record(render);
+                        if let Some(latency) = frame.latency {
+                            remote_peer.latency_meter.record(LatencyBreakdown {
+                                decode: decode,
+                                render: render,
+                                ..latency
+                            });
+                        }
                     },
                     Err(e) => {
-                        chatbox.error(&e.to_string());
-                        chatbox.draw(&mut stdout)?;
+                        info!("unable to display frame from {}: {}", participant, e);
+                        client.lock().await.request_keyframe(&participant);
+
+                        if !peer_video_panel.recovering() {
+                            peer_video_panel.set_recovering(true);
+                            peer_video_panel.draw(&mut stdout)?;
+                        }
                         continue;
                     },
                 }
             },
+
+            _ = status_interval.tick() => {
+                drop(client_guard);
+
+                let client_ref = client.lock().await;
+                let send_bitrate = client_ref.send_bitrate();
+                let receive_stats = remote_peers
+                    .keys()
+                    .map(|participant| (participant.clone(), client_ref.receive_stats(participant)))
+                    .collect::<Vec<_>>();
+                let connection_states = remote_peers
+                    .keys()
+                    .map(|participant| (participant.clone(), client_ref.connection_state(participant)))
+                    .collect::<BTreeMap<_, _>>();
+                let skipped_frames = client_ref.skipped_frames();
+                let dropped_encoded_frames = client_ref.dropped_encoded_frames();
+                let sent_frames = client_ref.sent_frames();
+                let peer_connections = client_ref.peer_connections();
+                drop(client_ref);
+
+                let mut reports = Vec::new();
+                for peer_connection in &peer_connections {
+                    reports.push(peer_connection.get_stats().await);
+                }
+                let ice_gathering = peer_connections.iter().any(|peer_connection| {
+                    peer_connection.ice_gathering_state() == RTCIceGatheringState::Gathering
+                });
+                // the overlay shows the worst of the incoming streams
+                let worst_receive_stats = receive_stats
+                    .iter()
+                    .map(|(_, receive_stats)| *receive_stats)
+                    .max_by(|a, b| a.loss_fraction().total_cmp(&b.loss_fraction()))
+                    .unwrap_or_else(ReceiveStats::default);
+                let mut call_stats = stats_collector.collect(
+                    &reports,
+                    &sent_frames,
+                    &rendered_frames,
+                    &worst_receive_stats,
+                );
+                call_stats.ice_gathering = ice_gathering;
+                stats_panel.update(call_stats);
+                stats_panel.draw(&mut stdout)?;
+
+                let dropped = (
+                    local_video_receiver.as_ref().map_or(0, |receiver| receiver.dropped()),
+                    skipped_frames,
+                    dropped_encoded_frames,
+                    remote_peers
+                        .values()
+                        .filter_map(|remote_peer| remote_peer.frames.as_ref())
+                        .map(|frames| frames.dropped())
+                        .sum(),
+                );
+                if dropped != dropped_frames {
+                    info!(
+                        "dropped frames: preview {}, encode {}, send {}, decode {}",
+                        dropped.0, dropped.1, dropped.2, dropped.3
+                    );
+                    dropped_frames = dropped;
+                }
+
+                if !viewer {
+                    let title = match local_video_panel.terminal() {
+                        Some(_) => Some(String::from("terminal")),
+                        None => send_bitrate.map(|bitrate| format!("↑ {} kbps", bitrate / 1000)),
+                    };
+                    local_video_panel.set_title(title);
+                    local_video_panel.draw_border(&mut stdout)?;
+                    if local_video_panel.expire_reaction() {
+                        local_video_panel.draw(&mut stdout)?;
+                    }
+                }
+                for remote_peer in remote_peers.values_mut() {
+                    if remote_peer.panel.expire_reaction() {
+                        remote_peer.panel.draw(&mut stdout)?;
+                    }
+                }
+
+                for (participant, receive_stats) in receive_stats {
+                    let remote_peer = match remote_peers.get_mut(&participant) {
+                        Some(remote_peer) => remote_peer,
+                        None => continue,
+                    };
+                    let peer_video_panel = &mut remote_peer.panel;
+
+                    // e.g. disconnected while the connection recovers
+                    if let Some(Some(state)) = connection_states.get(&participant)
+                        && *state != RTCPeerConnectionState::Connected
+                    {
+                        peer_video_panel.set_title(Some(format!("{} · {}", participant.username, state)));
+                        peer_video_panel.draw_border(&mut stdout)?;
+                        continue;
+                    }
+
+                    if peer_video_panel.terminal().is_some() {
+                        let title = match remote_peer.control {
+                            true => format!("{} · terminal · you can type", participant.username),
+                            false => format!("{} · terminal", participant.username),
+                        };
+                        peer_video_panel.set_title(Some(title));
+                        peer_video_panel.draw_border(&mut stdout)?;
+                        continue;
+                    }
+
+                    if peer_video_panel.stopped() {
+                        peer_video_panel.set_title(Some(format!("{} · stopped", participant.username)));
+                        peer_video_panel.draw_border(&mut stdout)?;
+                        continue;
+                    }
+
+                    peer_video_panel.set_latency(remote_peer.latency_meter.breakdown());
+                    if receive_stats.received > 0 {
+                        let title = format!(
+                            "{} · loss {:.1}% · jitter {} ms",
+                            participant.username,
+                            receive_stats.loss_fraction() * 100.0,
+                            receive_stats.jitter.as_millis()
+                        );
+                        peer_video_panel.set_title(Some(title));
+                    } else {
+                        peer_video_panel.set_title(Some(format!("{} · waiting for video", participant.username)));
+                    }
+                    peer_video_panel.draw_border(&mut stdout)?;
+                }
+            },
         }
     }
 
     stdout
+        .execute(DisableMouseCapture)?
         .execute(MoveTo(0, 0))?
         .execute(Clear(ClearType::All))?
         .execute(crossterm::cursor::Show)?;
@@ -0,0 +1,418 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveTo,
+    style::{Color, Print, PrintStyledContent, Stylize},
+};
+use webrtc::stats::{StatsReport, StatsReportType};
+
+use crate::{layout::Drawable, video::jitter::ReceiveStats};
+
+// Counts frames along with the time spent on them, e.g. encoding or rendering
+#[derive(Default)]
+pub struct FrameCounter {
+    frames: AtomicU64,
+    busy_micros: AtomicU64,
+}
+
+impl FrameCounter {
+    pub fn record(&self, busy: Duration) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.busy_micros
+            .fetch_add(busy.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn totals(&self) -> (u64, u64) {
+        (
+            self.frames.load(Ordering::Relaxed),
+            self.busy_micros.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CallStats {
+    pub send_fps: f32,
+    pub send_bitrate: u64,
+    pub send_loss: f32,
+    pub encode_time: Duration,
+    pub receive_fps: f32,
+    pub receive_bitrate: u64,
+    pub receive_loss: f32,
+    pub jitter: Duration,
+    pub render_time: Duration,
+    pub round_trip_time: Option<Duration>,
+    pub candidate_pair: Option<String>,
+    // some peer connection is still gathering its own candidates
+    pub ice_gathering: bool,
+}
+
+struct Sample {
+    at: Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    sent: (u64, u64),
+    received: (u64, u64),
+}
+
+// Turns the cumulative counters of consecutive reports into per-second rates
+pub struct StatsCollector {
+    previous: Option<Sample>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self { previous: None }
+    }
+
+    // one report per peer connection
+    pub fn collect(
+        &mut self,
+        reports: &[StatsReport],
+        sent: &FrameCounter,
+        received: &FrameCounter,
+        receive_stats: &ReceiveStats,
+    ) -> CallStats {
+        let mut stats = CallStats {
+            receive_loss: receive_stats.loss_fraction(),
+            jitter: receive_stats.jitter,
+            ..Default::default()
+        };
+
+        let mut bytes_sent = 0;
+        let mut bytes_received = 0;
+        let mut candidate_pairs = Vec::new();
+        for report in reports {
+            for stats_type in report.reports.values() {
+                match stats_type {
+                    StatsReportType::OutboundRTP(outbound) => bytes_sent += outbound.bytes_sent,
+                    StatsReportType::InboundRTP(inbound) => {
+                        bytes_received += inbound.bytes_received
+                    }
+                    StatsReportType::RemoteInboundRTP(remote_inbound) => {
+                        stats.send_loss = stats.send_loss.max(remote_inbound.fraction_lost as f32);
+                        if let Some(rtt) = remote_inbound.round_trip_time {
+                            stats.round_trip_time = stats
+                                .round_trip_time
+                                .max(Some(Duration::from_secs_f64(rtt)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some((pair, rtt)) = selected_candidate_pair(report) {
+                candidate_pairs.push(pair);
+                stats.round_trip_time = stats.round_trip_time.max(rtt);
+            }
+        }
+        if !candidate_pairs.is_empty() {
+            stats.candidate_pair = Some(candidate_pairs.join(" · "));
+        }
+
+        let sample = Sample {
+            at: Instant::now(),
+            bytes_sent: bytes_sent,
+            bytes_received: bytes_received,
+            sent: sent.totals(),
+            received: received.totals(),
+        };
+        if let Some(previous) = &self.previous {
+            let elapsed = sample
+                .at
+                .duration_since(previous.at)
+                .as_secs_f64()
+                .max(0.001);
+            let per_second = |from: u64, to: u64| to.saturating_sub(from) as f64 / elapsed;
+            let per_frame = |(frames, micros): (u64, u64), (to_frames, to_micros): (u64, u64)| {
+                let frames = to_frames.saturating_sub(frames);
+                match frames {
+                    0 => Duration::ZERO,
+                    _ => Duration::from_micros(to_micros.saturating_sub(micros) / frames),
+                }
+            };
+
+            stats.send_bitrate = (per_second(previous.bytes_sent, sample.bytes_sent) * 8.0) as u64;
+            stats.receive_bitrate =
+                (per_second(previous.bytes_received, sample.bytes_received) * 8.0) as u64;
+            stats.send_fps = per_second(previous.sent.0, sample.sent.0) as f32;
+            stats.receive_fps = per_second(previous.received.0, sample.received.0) as f32;
+            stats.encode_time = per_frame(previous.sent, sample.sent);
+            stats.render_time = per_frame(previous.received, sample.received);
+        }
+        self.previous = Some(sample);
+
+        stats
+    }
+}
+
+fn selected_candidate_pair(report: &StatsReport) -> Option<(String, Option<Duration>)> {
+    let pair = report
+        .reports
+        .values()
+        .find_map(|stats_type| match stats_type {
+            StatsReportType::CandidatePair(pair) if pair.nominated => Some(pair),
+            _ => None,
+        })?;
+
+    let candidate = |id: &str| match report.reports.get(id) {
+        Some(StatsReportType::LocalCandidate(candidate))
+        | Some(StatsReportType::RemoteCandidate(candidate)) => Some(format!(
+            "{} {}:{}",
+            candidate.candidate_type, candidate.ip, candidate.port
+        )),
+        _ => None,
+    };
+    let local = candidate(&pair.local_candidate_id)?;
+    let remote = candidate(&pair.remote_candidate_id)?;
+
+    let rtt = Some(pair.current_round_trip_time)
+        .filter(|rtt| *rtt > 0.0)
+        .map(Duration::from_secs_f64);
+    Some((format!("{} ⇄ {}", local, remote), rtt))
+}
+
+struct Sparkline {
+    values: VecDeque<f32>,
+}
+
+impl Sparkline {
+    const CAPACITY: usize = 30;
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    fn new() -> Self {
+        Self {
+            values: VecDeque::with_capacity(Self::CAPACITY),
+        }
+    }
+
+    fn push(&mut self, value: f32) {
+        if self.values.len() == Self::CAPACITY {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    fn render(&self, width: usize) -> String {
+        let max = self.values.iter().copied().fold(0.0, f32::max);
+        let skip = self.values.len().saturating_sub(width);
+        self.values
+            .iter()
+            .skip(skip)
+            .map(|value| {
+                if max <= 0.0 {
+                    return Self::BARS[0];
+                }
+                let index = (value / max * (Self::BARS.len() - 1) as f32).round() as usize;
+                Self::BARS[index.min(Self::BARS.len() - 1)]
+            })
+            .collect()
+    }
+}
+
+// Drawn over the top of the chatbox while toggled on with /stats
+pub struct StatsPanel {
+    x: u16,
+    y: u16,
+    width: u16,
+    visible: bool,
+    stats: CallStats,
+    send_fps: Sparkline,
+    send_bitrate: Sparkline,
+    encode_time: Sparkline,
+    receive_fps: Sparkline,
+    receive_bitrate: Sparkline,
+    render_time: Sparkline,
+    round_trip_time: Sparkline,
+}
+
+impl StatsPanel {
+    const ROWS: u16 = 11;
+    const LABEL_WIDTH: usize = 10;
+    const VALUE_WIDTH: usize = 12;
+    const DIVIDER: &str = "─";
+
+    pub fn new(x: u16, y: u16, width: u16) -> Self {
+        Self {
+            x: x,
+            y: y,
+            width: width,
+            visible: false,
+            stats: CallStats::default(),
+            send_fps: Sparkline::new(),
+            send_bitrate: Sparkline::new(),
+            encode_time: Sparkline::new(),
+            receive_fps: Sparkline::new(),
+            receive_bitrate: Sparkline::new(),
+            render_time: Sparkline::new(),
+            round_trip_time: Sparkline::new(),
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // rows taken from the chatbox while visible
+    pub fn reserved_rows(&self) -> u16 {
+        if self.visible { Self::ROWS } else { 0 }
+    }
+
+    pub fn update(&mut self, stats: CallStats) {
+        self.send_fps.push(stats.send_fps);
+        self.send_bitrate.push(stats.send_bitrate as f32);
+        self.encode_time.push(stats.encode_time.as_secs_f32());
+        self.receive_fps.push(stats.receive_fps);
+        self.receive_bitrate.push(stats.receive_bitrate as f32);
+        self.render_time.push(stats.render_time.as_secs_f32());
+        self.round_trip_time.push(
+            stats
+                .round_trip_time
+                .map_or(0.0, |round_trip_time| round_trip_time.as_secs_f32()),
+        );
+        self.stats = stats;
+    }
+
+    fn rows(&self) -> Vec<(String, String, Option<&Sparkline>)> {
+        let stats = &self.stats;
+        vec![
+            (
+                String::from("↑ fps"),
+                format!("{:.0}", stats.send_fps),
+                Some(&self.send_fps),
+            ),
+            (
+                String::from("↑ bitrate"),
+                format_bitrate(stats.send_bitrate),
+                Some(&self.send_bitrate),
+            ),
+            (
+                String::from("↑ loss"),
+                format!("{:.1}%", stats.send_loss * 100.0),
+                None,
+            ),
+            (
+                String::from("↑ encode"),
+                format_millis(stats.encode_time),
+                Some(&self.encode_time),
+            ),
+            (
+                String::from("↓ fps"),
+                format!("{:.0}", stats.receive_fps),
+                Some(&self.receive_fps),
+            ),
+            (
+                String::from("↓ bitrate"),
+                format_bitrate(stats.receive_bitrate),
+                Some(&self.receive_bitrate),
+            ),
+            (
+                String::from("↓ loss"),
+                format!(
+                    "{:.1}% · jitter {}",
+                    stats.receive_loss * 100.0,
+                    format_millis(stats.jitter)
+                ),
+                None,
+            ),
+            (
+                String::from("↓ render"),
+                format_millis(stats.render_time),
+                Some(&self.render_time),
+            ),
+            (
+                String::from("rtt"),
+                stats
+                    .round_trip_time
+                    .map_or(String::from("-"), format_millis),
+                Some(&self.round_trip_time),
+            ),
+            (
+                String::from("ice"),
+                match (&stats.candidate_pair, stats.ice_gathering) {
+                    (Some(pair), false) => pair.clone(),
+                    (Some(pair), true) => format!("{} · gathering", pair),
+                    (None, false) => String::from("-"),
+                    (None, true) => String::from("gathering"),
+                },
+                None,
+            ),
+        ]
+    }
+}
+
+fn format_bitrate(bitrate: u64) -> String {
+    if bitrate >= 1_000_000 {
+        format!("{:.1} Mbps", bitrate as f64 / 1_000_000.0)
+    } else {
+        format!("{} kbps", bitrate / 1000)
+    }
+}
+
+fn format_millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+impl Drawable for StatsPanel {
+    fn draw(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        if !self.visible {
+            return Ok(());
+        }
+
+        let width = self.width as usize;
+        let sparkline_width = width.saturating_sub(Self::LABEL_WIDTH + Self::VALUE_WIDTH);
+        for (i, (label, value, sparkline)) in self.rows().into_iter().enumerate() {
+            let mut line = format!(
+                "{:<label_width$}{:<value_width$}",
+                label,
+                value,
+                label_width = Self::LABEL_WIDTH,
+                value_width = Self::VALUE_WIDTH
+            );
+            if let Some(sparkline) = sparkline {
+                line.push_str(&sparkline.render(sparkline_width));
+            }
+            let line = line.chars().take(width).collect::<String>();
+            let padding = width.saturating_sub(line.chars().count());
+
+            stdout
+                .queue(MoveTo(self.x, self.y + i as u16))?
+                .queue(Print(line))?
+                .queue(Print(" ".repeat(padding)))?;
+        }
+
+        stdout
+            .queue(MoveTo(self.x, self.y + Self::ROWS - 1))?
+            .queue(PrintStyledContent(
+                Self::DIVIDER.repeat(width).with(Color::DarkGrey),
+            ))?;
+
+        stdout.flush()
+    }
+
+    fn x(&self) -> u16 {
+        self.x
+    }
+
+    fn y(&self) -> u16 {
+        self.y
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        Self::ROWS
+    }
+}
@@ -0,0 +1,165 @@
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveTo,
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    style::{Attribute, Color, ContentStyle, Print, PrintStyledContent, StyledContent},
+};
+
+const DEFAULT_COLUMNS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+
+// A terminal shared by us or a peer, replayed through a terminal emulator so that it can be
+// drawn into a panel
+pub struct TerminalScreen {
+    parser: vt100::Parser,
+}
+
+impl TerminalScreen {
+    pub fn new() -> Self {
+        Self {
+            parser: vt100::Parser::new(DEFAULT_ROWS, DEFAULT_COLUMNS, 0),
+        }
+    }
+
+    pub fn process(&mut self, data: &[u8]) {
+        self.parser.process(data);
+    }
+
+    pub fn resize(&mut self, columns: u16, rows: u16) {
+        self.parser.screen_mut().set_size(rows, columns);
+    }
+
+    // the bytes a terminal would send for the key, or None for keys it has no sequence for
+    pub fn key_bytes(&self, key_event: &KeyEvent) -> Option<Vec<u8>> {
+        let application_cursor = self.parser.screen().application_cursor();
+        let cursor = |code: u8| {
+            if application_cursor {
+                vec![0x1b, b'O', code]
+            } else {
+                vec![0x1b, b'[', code]
+            }
+        };
+
+        let bytes = match key_event.code {
+            KeyCode::Char(c) if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                match c.to_ascii_lowercase() {
+                    c @ 'a'..='z' => vec![c as u8 - b'a' + 1],
+                    ' ' | '@' => vec![0],
+                    '[' => vec![0x1b],
+                    '\\' => vec![0x1c],
+                    '^' => vec![0x1e],
+                    '_' => vec![0x1f],
+                    _ => return None,
+                }
+            }
+            KeyCode::Char(c) => c.to_string().into_bytes(),
+            KeyCode::Enter => vec![b'\r'],
+            KeyCode::Backspace => vec![0x7f],
+            KeyCode::Tab => vec![b'\t'],
+            KeyCode::BackTab => b"\x1b[Z".to_vec(),
+            KeyCode::Esc => vec![0x1b],
+            KeyCode::Up => cursor(b'A'),
+            KeyCode::Down => cursor(b'B'),
+            KeyCode::Right => cursor(b'C'),
+            KeyCode::Left => cursor(b'D'),
+            KeyCode::Home => cursor(b'H'),
+            KeyCode::End => cursor(b'F'),
+            KeyCode::Insert => b"\x1b[2~".to_vec(),
+            KeyCode::Delete => b"\x1b[3~".to_vec(),
+            KeyCode::PageUp => b"\x1b[5~".to_vec(),
+            KeyCode::PageDown => b"\x1b[6~".to_vec(),
+            KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+            KeyCode::F(n @ 5..=12) => {
+                let code = [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5];
+                format!("\x1b[{}~", code).into_bytes()
+            }
+            _ => return None,
+        };
+
+        // alt sends an escape first
+        if key_event.modifiers.contains(KeyModifiers::ALT) {
+            return Some([vec![0x1b], bytes].concat());
+        }
+        Some(bytes)
+    }
+
+    // draws as much of the screen as fits, and clears whatever is left of the area
+    pub fn draw(
+        &self,
+        stdout: &mut std::io::Stdout,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+    ) -> Result<(), std::io::Error> {
+        let screen = self.parser.screen();
+        let (rows, columns) = screen.size();
+        let (cursor_row, cursor_column) = screen.cursor_position();
+
+        for row in 0..height {
+            stdout.queue(MoveTo(x, y + row))?;
+
+            let mut drawn = 0;
+            if row < rows {
+                for column in 0..columns.min(width) {
+                    let cell = match screen.cell(row, column) {
+                        Some(cell) => cell,
+                        None => break,
+                    };
+                    if cell.is_wide_continuation() {
+                        continue;
+                    }
+                    // a wide character at the edge would spill over the border
+                    if cell.is_wide() && column + 1 >= width {
+                        break;
+                    }
+
+                    let cursor =
+                        !screen.hide_cursor() && row == cursor_row && column == cursor_column;
+                    stdout.queue(PrintStyledContent(styled_cell(cell, cursor)))?;
+                    drawn = column + if cell.is_wide() { 2 } else { 1 };
+                }
+            }
+
+            stdout.queue(Print(" ".repeat(width.saturating_sub(drawn) as usize)))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn styled_cell(cell: &vt100::Cell, cursor: bool) -> StyledContent<String> {
+    let contents = if cell.has_contents() {
+        cell.contents().to_owned()
+    } else {
+        String::from(" ")
+    };
+
+    let mut style = ContentStyle {
+        foreground_color: Some(color(cell.fgcolor())),
+        background_color: Some(color(cell.bgcolor())),
+        ..Default::default()
+    };
+    if cell.bold() {
+        style.attributes.set(Attribute::Bold);
+    }
+    if cell.italic() {
+        style.attributes.set(Attribute::Italic);
+    }
+    if cell.underline() {
+        style.attributes.set(Attribute::Underlined);
+    }
+    if cell.inverse() != cursor {
+        style.attributes.set(Attribute::Reverse);
+    }
+
+    StyledContent::new(style, contents)
+}
+
+fn color(color: vt100::Color) -> Color {
+    match color {
+        vt100::Color::Default => Color::Reset,
+        vt100::Color::Idx(index) => Color::AnsiValue(index),
+        vt100::Color::Rgb(r, g, b) => Color::Rgb { r: r, g: g, b: b },
+    }
+}
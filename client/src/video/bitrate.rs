@@ -0,0 +1,389 @@
+use std::time::{Duration, Instant};
+
+use webrtc::rtcp::{
+    packet::Packet,
+    payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+    receiver_report::ReceiverReport,
+    transport_feedbacks::transport_layer_cc::{PacketStatusChunk, SymbolTypeTcc, TransportLayerCc},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderTarget {
+    pub bitrate: u32,
+    pub frame_rate: f32,
+    pub max_width: usize,
+    pub max_height: usize,
+}
+
+// (minimum bitrate, frame rate, max width, max height)
+const TIERS: [(u32, f32, usize, usize); 4] = [
+    (1_500_000, 30.0, 1280, 720),
+    (600_000, 30.0, 640, 360),
+    (250_000, 20.0, 320, 180),
+    (0, 15.0, 160, 90),
+];
+
+impl EncoderTarget {
+    pub fn from_bitrate(bitrate: u32) -> Self {
+        let (_, frame_rate, max_width, max_height) = TIERS
+            .iter()
+            .find(|(minimum, ..)| bitrate >= *minimum)
+            .copied()
+            .unwrap_or(TIERS[TIERS.len() - 1]);
+
+        Self {
+            bitrate: bitrate,
+            frame_rate: frame_rate,
+            max_width: max_width,
+            max_height: max_height,
+        }
+    }
+}
+
+impl Default for EncoderTarget {
+    fn default() -> Self {
+        Self::from_bitrate(BandwidthEstimator::INITIAL_BITRATE)
+    }
+}
+
+// Loss-based estimator loosely following the loss controller in
+// https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-6,
+// capped by the receiver's REMB when one is available. Transport-wide feedback is only used
+// for the packets it reports lost, since a delay-based signal would need the send time of
+// every packet, which the interceptors don't hand out.
+pub(crate) struct BandwidthEstimator {
+    estimate: u32,
+    remb: Option<u32>,
+    loss: Option<f32>,
+    last_update: Instant,
+    last_target: Option<EncoderTarget>,
+}
+
+impl BandwidthEstimator {
+    const INITIAL_BITRATE: u32 = 800_000;
+    const MIN_BITRATE: u32 = 100_000;
+    const MAX_BITRATE: u32 = 2_500_000;
+    const UPDATE_INTERVAL: Duration = Duration::from_millis(1000);
+
+    pub(crate) fn new() -> Self {
+        Self {
+            estimate: Self::INITIAL_BITRATE,
+            remb: None,
+            loss: None,
+            last_update: Instant::now(),
+            last_target: None,
+        }
+    }
+
+    pub(crate) fn on_rtcp(&mut self, packet: &(dyn Packet + Send + Sync)) {
+        let packet = packet.as_any();
+        if let Some(receiver_report) = packet.downcast_ref::<ReceiverReport>() {
+            self.on_receiver_report(receiver_report);
+        } else if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+            self.remb = Some(remb.bitrate as u32);
+        } else if let Some(transport_cc) = packet.downcast_ref::<TransportLayerCc>() {
+            self.on_transport_cc(transport_cc);
+        }
+    }
+
+    fn on_receiver_report(&mut self, receiver_report: &ReceiverReport) {
+        let worst = receiver_report
+            .reports
+            .iter()
+            .map(|report| report.fraction_lost as f32 / 256.0)
+            .fold(None, |worst: Option<f32>, loss| {
+                Some(worst.map_or(loss, |worst| worst.max(loss)))
+            });
+
+        if let Some(loss) = worst {
+            self.record_loss(loss);
+        }
+    }
+
+    fn on_transport_cc(&mut self, transport_cc: &TransportLayerCc) {
+        let (mut received, mut lost) = (0usize, 0usize);
+        let mut count = |symbol: &SymbolTypeTcc, run: usize| match symbol {
+            SymbolTypeTcc::PacketNotReceived => lost += run,
+            _ => received += run,
+        };
+
+        for chunk in &transport_cc.packet_chunks {
+            match chunk {
+                PacketStatusChunk::RunLengthChunk(chunk) => {
+                    count(&chunk.packet_status_symbol, chunk.run_length as usize)
+                }
+                PacketStatusChunk::StatusVectorChunk(chunk) => {
+                    chunk.symbol_list.iter().for_each(|symbol| count(symbol, 1))
+                }
+            }
+        }
+
+        // the last chunk may be padded past packet_status_count
+        let total = (transport_cc.packet_status_count as usize).min(received + lost);
+        if total > 0 {
+            let lost = total.saturating_sub(received);
+            self.record_loss(lost as f32 / total as f32);
+        }
+    }
+
+    fn record_loss(&mut self, loss: f32) {
+        self.loss = Some(self.loss.map_or(loss, |previous| previous.max(loss)));
+    }
+
+    pub(crate) fn poll_target(&mut self) -> Option<EncoderTarget> {
+        if self.last_update.elapsed() < Self::UPDATE_INTERVAL {
+            return None;
+        }
+        self.last_update = Instant::now();
+
+        let estimate = match self.loss.take() {
+            Some(loss) if loss > 0.1 => (self.estimate as f32 * (1.0 - 0.5 * loss)) as u32,
+            Some(loss) if loss < 0.02 => (self.estimate as f32 * 1.08) as u32,
+            Some(_) => self.estimate,
+            None => self.estimate,
+        };
+        let ceiling = self
+            .remb
+            .unwrap_or(Self::MAX_BITRATE)
+            .min(Self::MAX_BITRATE);
+        self.estimate = estimate.clamp(Self::MIN_BITRATE, ceiling.max(Self::MIN_BITRATE));
+
+        let target = EncoderTarget::from_bitrate(self.estimate);
+        let changed = match &self.last_target {
+            Some(last) => {
+                last.max_width != target.max_width
+                    || last.bitrate.abs_diff(target.bitrate) > last.bitrate / 10
+            }
+            None => true,
+        };
+        if !changed {
+            return None;
+        }
+
+        self.last_target = Some(target);
+        Some(target)
+    }
+}
+
+pub(crate) struct BitrateMeter {
+    bytes: usize,
+    window_start: Instant,
+}
+
+impl BitrateMeter {
+    const WINDOW: Duration = Duration::from_millis(1000);
+
+    pub(crate) fn new() -> Self {
+        Self {
+            bytes: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, bytes: usize) -> Option<u32> {
+        self.bytes += bytes;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Self::WINDOW {
+            return None;
+        }
+
+        let bitrate = (self.bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u32;
+        self.bytes = 0;
+        self.window_start = Instant::now();
+        Some(bitrate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use webrtc::rtcp::{
+        reception_report::ReceptionReport, transport_feedbacks::transport_layer_cc::RunLengthChunk,
+    };
+
+    // lets poll_target update right away
+    fn due(estimator: &mut BandwidthEstimator) {
+        estimator.last_update = Instant::now() - BandwidthEstimator::UPDATE_INTERVAL;
+    }
+
+    fn receiver_report(fraction_lost: u8) -> ReceiverReport {
+        ReceiverReport {
+            reports: vec![ReceptionReport {
+                fraction_lost: fraction_lost,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn transport_cc(received: u16, lost: u16) -> TransportLayerCc {
+        let chunk = |symbol, run_length| {
+            PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                packet_status_symbol: symbol,
+                run_length: run_length,
+                ..Default::default()
+            })
+        };
+        TransportLayerCc {
+            packet_status_count: received + lost,
+            packet_chunks: vec![
+                chunk(SymbolTypeTcc::PacketReceivedSmallDelta, received),
+                chunk(SymbolTypeTcc::PacketNotReceived, lost),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn target_tiers() {
+        let target = EncoderTarget::from_bitrate(2_000_000);
+        assert_eq!(
+            (target.frame_rate, target.max_width, target.max_height),
+            (30.0, 1280, 720)
+        );
+
+        let target = EncoderTarget::from_bitrate(600_000);
+        assert_eq!((target.max_width, target.max_height), (640, 360));
+
+        let target = EncoderTarget::from_bitrate(300_000);
+        assert_eq!(
+            (target.frame_rate, target.max_width, target.max_height),
+            (20.0, 320, 180)
+        );
+
+        let target = EncoderTarget::from_bitrate(0);
+        assert_eq!(
+            (target.frame_rate, target.max_width, target.max_height),
+            (15.0, 160, 90)
+        );
+        assert_eq!(target.bitrate, 0);
+    }
+
+    #[test]
+    fn increase_without_loss() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_receiver_report(&receiver_report(0));
+        due(&mut estimator);
+
+        estimator.poll_target();
+        assert_eq!(
+            estimator.estimate,
+            (BandwidthEstimator::INITIAL_BITRATE as f32 * 1.08) as u32
+        );
+    }
+
+    #[test]
+    fn hold_with_moderate_loss() {
+        let mut estimator = BandwidthEstimator::new();
+        // 5% is between the thresholds
+        estimator.on_receiver_report(&receiver_report(13));
+        due(&mut estimator);
+
+        estimator.poll_target();
+        assert_eq!(estimator.estimate, BandwidthEstimator::INITIAL_BITRATE);
+    }
+
+    #[test]
+    fn decrease_with_loss() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_receiver_report(&receiver_report(128));
+        due(&mut estimator);
+
+        let target = estimator.poll_target().unwrap();
+        assert_eq!(target.bitrate, 600_000);
+    }
+
+    #[test]
+    fn transport_cc_loss() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_transport_cc(&transport_cc(50, 50));
+        assert_eq!(estimator.loss, Some(0.5));
+
+        // padding past packet_status_count isn't counted as lost
+        let mut padded = transport_cc(10, 0);
+        padded
+            .packet_chunks
+            .push(PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                packet_status_symbol: SymbolTypeTcc::PacketNotReceived,
+                run_length: 5,
+                ..Default::default()
+            }));
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_transport_cc(&padded);
+        assert_eq!(estimator.loss, Some(0.0));
+    }
+
+    #[test]
+    fn worst_loss_wins() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_transport_cc(&transport_cc(100, 0));
+        estimator.on_receiver_report(&receiver_report(128));
+        estimator.on_transport_cc(&transport_cc(100, 0));
+        assert_eq!(estimator.loss, Some(0.5));
+    }
+
+    #[test]
+    fn clamp_to_minimum() {
+        let mut estimator = BandwidthEstimator::new();
+        for _ in 0..20 {
+            estimator.on_receiver_report(&receiver_report(255));
+            due(&mut estimator);
+            estimator.poll_target();
+        }
+        assert_eq!(estimator.estimate, BandwidthEstimator::MIN_BITRATE);
+    }
+
+    #[test]
+    fn clamp_to_maximum() {
+        let mut estimator = BandwidthEstimator::new();
+        for _ in 0..50 {
+            estimator.on_receiver_report(&receiver_report(0));
+            due(&mut estimator);
+            estimator.poll_target();
+        }
+        assert_eq!(estimator.estimate, BandwidthEstimator::MAX_BITRATE);
+    }
+
+    #[test]
+    fn clamp_to_remb() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_rtcp(&ReceiverEstimatedMaximumBitrate {
+            bitrate: 300_000.0,
+            ..Default::default()
+        });
+        due(&mut estimator);
+        assert_eq!(estimator.poll_target().unwrap().bitrate, 300_000);
+
+        // a REMB below the minimum doesn't push the estimate under it
+        estimator.on_rtcp(&ReceiverEstimatedMaximumBitrate {
+            bitrate: 10_000.0,
+            ..Default::default()
+        });
+        due(&mut estimator);
+        estimator.poll_target();
+        assert_eq!(estimator.estimate, BandwidthEstimator::MIN_BITRATE);
+    }
+
+    #[test]
+    fn poll_target_waits_for_interval() {
+        let mut estimator = BandwidthEstimator::new();
+        estimator.on_receiver_report(&receiver_report(128));
+        assert!(estimator.poll_target().is_none());
+        assert_eq!(estimator.loss, Some(0.5));
+    }
+
+    #[test]
+    fn small_changes_are_not_reported() {
+        let mut estimator = BandwidthEstimator::new();
+        due(&mut estimator);
+        assert!(estimator.poll_target().is_some());
+
+        // 8% up is within the 10% that isn't worth reconfiguring the encoder for
+        estimator.on_receiver_report(&receiver_report(0));
+        due(&mut estimator);
+        assert!(estimator.poll_target().is_none());
+        assert!(estimator.estimate > BandwidthEstimator::INITIAL_BITRATE);
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::VecDeque,
+    future::poll_fn,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::task::AtomicWaker;
+use tokio::sync::mpsc::error::SendError;
+
+struct Shared<T> {
+    frames: Mutex<VecDeque<T>>,
+    capacity: usize,
+    waker: AtomicWaker,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    dropped: Arc<AtomicU64>,
+}
+
+// A bounded channel where the newest frame always wins: once `capacity` frames are queued,
+// sending evicts the oldest one instead of waiting, so a slow consumer never builds up delay.
+pub fn frame_channel<T>(capacity: usize) -> (FrameSender<T>, FrameReceiver<T>) {
+    let shared = Arc::new(Shared {
+        frames: Mutex::new(VecDeque::with_capacity(capacity + 1)),
+        capacity: capacity.max(1),
+        waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+        dropped: Arc::new(AtomicU64::new(0)),
+    });
+
+    (
+        FrameSender {
+            shared: shared.clone(),
+        },
+        FrameReceiver { shared: shared },
+    )
+}
+
+pub struct FrameSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> FrameSender<T> {
+    // returns the frame that was evicted to make room, if any
+    pub fn send(&self, frame: T) -> Result<Option<T>, SendError<T>> {
+        if self.shared.receiver_closed.load(Ordering::Acquire) {
+            return Err(SendError(frame));
+        }
+
+        let evicted = {
+            let mut frames = self
+                .shared
+                .frames
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            frames.push_back(frame);
+            if frames.len() > self.shared.capacity {
+                frames.pop_front()
+            } else {
+                None
+            }
+        };
+        if evicted.is_some() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.shared.waker.wake();
+        Ok(evicted)
+    }
+}
+
+impl<T> Clone for FrameSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for FrameSender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.waker.wake();
+        }
+    }
+}
+
+pub struct FrameReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> FrameReceiver<T> {
+    fn try_recv(&self) -> Option<T> {
+        self.shared
+            .frames
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(frame) = self.try_recv() {
+            return Poll::Ready(Some(frame));
+        }
+
+        // register before checking again so that a frame sent in between isn't missed
+        self.shared.waker.register(cx.waker());
+        if let Some(frame) = self.try_recv() {
+            return Poll::Ready(Some(frame));
+        }
+
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    // throws away frames that are queued but no longer wanted
+    pub fn clear(&mut self) {
+        self.shared
+            .frames
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    // total number of frames evicted before they were received
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_counter(&self) -> Arc<AtomicU64> {
+        self.shared.dropped.clone()
+    }
+}
+
+impl<T> Drop for FrameReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+    }
+}
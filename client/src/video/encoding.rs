@@ -8,7 +8,12 @@ pub(crate) enum NalType {
     SPS = 7,
     PPS = 8,
     STAP_A = 24,
+    STAP_B = 25,
+    MTAP16 = 26,
+    MTAP24 = 27,
     FU_A = 28,
+    FU_B = 29,
+    Reserved = 254,
     Unknown = 255,
 }
 
@@ -20,7 +25,12 @@ impl From<u8> for NalType {
             7 => NalType::SPS,
             8 => NalType::PPS,
             24 => NalType::STAP_A,
+            25 => NalType::STAP_B,
+            26 => NalType::MTAP16,
+            27 => NalType::MTAP24,
             28 => NalType::FU_A,
+            29 => NalType::FU_B,
+            0 | 30 | 31 => NalType::Reserved,
             _ => NalType::Unknown,
         }
     }
@@ -28,68 +38,215 @@ impl From<u8> for NalType {
 
 const NAL_PREFIX_CODE: [u8; 4] = [0, 0, 0, 1];
 
-pub fn convert_payload_to_nal_units(
-    payload: &[u8],
-    nal_buffer: &mut Vec<u8>,
-) -> Option<Vec<Vec<u8>>> {
-    if payload.is_empty() {
-        return None;
+const FORBIDDEN_BIT_MASK: u8 = 0x80;
+const NRI_MASK: u8 = 0x60;
+const NAL_TYPE_MASK: u8 = 0x1F;
+
+const FU_START_MASK: u8 = 0x80;
+const FU_END_MASK: u8 = 0x40;
+
+struct Fragment {
+    nal_header: u8,
+    next_sequence_number: u16,
+    buffer: Vec<u8>,
+}
+
+// Depacketizes H.264 RTP payloads as described in https://datatracker.ietf.org/doc/html/rfc6184,
+// producing NAL units in Annex B format. Interleaved payloads (STAP-B, MTAP and FU-B) are
+// emitted in transmission order, since the jitter buffer already hands over whole frames.
+pub struct H264Depacketizer {
+    fragment: Option<Fragment>,
+}
+
+impl H264Depacketizer {
+    pub fn new() -> Self {
+        Self { fragment: None }
+    }
+
+    pub fn reset(&mut self) {
+        self.fragment = None;
+    }
+
+    pub fn depacketize(
+        &mut self,
+        sequence_number: u16,
+        payload: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let nal_header = match payload.first() {
+            Some(nal_header) => *nal_header,
+            None => return Err(Error::EmptyPayload),
+        };
+        if nal_header & FORBIDDEN_BIT_MASK != 0 {
+            return Err(Error::ForbiddenBitSet);
+        }
+
+        let nal_type = nal_header & NAL_TYPE_MASK;
+        match NalType::from(nal_type) {
+            NalType::STAP_A => convert_stap_to_nal_units(nal_type, &payload[1..]),
+            NalType::STAP_B => {
+                // skip the decoding order number
+                let aggregation_units = payload
+                    .get(3..)
+                    .ok_or(Error::TruncatedPacket { nal_type: nal_type })?;
+                convert_stap_to_nal_units(nal_type, aggregation_units)
+            }
+            NalType::MTAP16 => convert_mtap_to_nal_units(nal_type, payload, 2),
+            NalType::MTAP24 => convert_mtap_to_nal_units(nal_type, payload, 3),
+            NalType::FU_A => self.convert_fu_to_nal_units(sequence_number, payload, false),
+            NalType::FU_B => self.convert_fu_to_nal_units(sequence_number, payload, true),
+            NalType::Reserved => Err(Error::UnsupportedNalType { nal_type: nal_type }),
+            _ => Ok(vec![add_prefix_to_nal_unit(payload)]),
+        }
     }
 
-    let nal_header = payload[0];
-    let nal_type = NalType::from(nal_header & 0x1F);
+    fn convert_fu_to_nal_units(
+        &mut self,
+        sequence_number: u16,
+        payload: &[u8],
+        has_decoding_order_number: bool,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let fu_indicator = payload[0];
+        let fu_type = fu_indicator & NAL_TYPE_MASK;
+        let fu_header = *payload
+            .get(1)
+            .ok_or(Error::TruncatedPacket { nal_type: fu_type })?;
+
+        let start = fu_header & FU_START_MASK != 0;
+        let end = fu_header & FU_END_MASK != 0;
+        if start && end {
+            self.fragment = None;
+            return Err(Error::InvalidFragment);
+        }
+
+        // FU-B is only allowed for the first fragment, and carries a decoding order number
+        let header_size = if has_decoding_order_number { 4 } else { 2 };
+        if has_decoding_order_number && !start {
+            self.fragment = None;
+            return Err(Error::InvalidFragment);
+        }
+        let fragment_payload = match payload.get(header_size..) {
+            Some(fragment_payload) if !fragment_payload.is_empty() => fragment_payload,
+            _ => {
+                self.fragment = None;
+                return Err(Error::TruncatedPacket { nal_type: fu_type });
+            }
+        };
+
+        let nal_header =
+            (fu_indicator & (FORBIDDEN_BIT_MASK | NRI_MASK)) | (fu_header & NAL_TYPE_MASK);
+        if start {
+            let mut buffer = Vec::with_capacity(1 + fragment_payload.len());
+            buffer.push(nal_header);
+            buffer.extend_from_slice(fragment_payload);
+
+            let previous = self.fragment.replace(Fragment {
+                nal_header: nal_header,
+                next_sequence_number: sequence_number.wrapping_add(1),
+                buffer: buffer,
+            });
+            return match previous {
+                Some(_) => Err(Error::IncompleteFragment),
+                None => Ok(Vec::new()),
+            };
+        }
+
+        let mut fragment = match self.fragment.take() {
+            Some(fragment) => fragment,
+            None => return Err(Error::UnexpectedFragment),
+        };
+        if fragment.next_sequence_number != sequence_number {
+            return Err(Error::MissingFragment {
+                expected: fragment.next_sequence_number,
+                received: sequence_number,
+            });
+        }
+        if fragment.nal_header != nal_header {
+            return Err(Error::FragmentMismatch {
+                expected: fragment.nal_header & NAL_TYPE_MASK,
+                received: nal_header & NAL_TYPE_MASK,
+            });
+        }
+
+        fragment.buffer.extend_from_slice(fragment_payload);
+        if end {
+            return Ok(vec![add_prefix_to_nal_unit(&fragment.buffer)]);
+        }
 
-    match nal_type {
-        NalType::STAP_A => convert_stap_a_to_nal_units(&payload),
-        NalType::FU_A => convert_fu_a_to_nal_units(&payload, nal_buffer),
-        _ => Some(vec![add_prefix_to_nal_unit(&payload)]),
+        fragment.next_sequence_number = sequence_number.wrapping_add(1);
+        self.fragment = Some(fragment);
+        Ok(Vec::new())
     }
 }
 
-fn convert_stap_a_to_nal_units(payload: &[u8]) -> Option<Vec<Vec<u8>>> {
+fn convert_stap_to_nal_units(
+    nal_type: u8,
+    aggregation_units: &[u8],
+) -> Result<Vec<Vec<u8>>, Error> {
     let mut nal_units = Vec::new();
 
-    let mut offset = 1;
-    while offset + 2 <= payload.len() {
-        let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+    let mut offset = 0;
+    while offset < aggregation_units.len() {
+        let size = match aggregation_units.get(offset..offset + 2) {
+            Some(size) => u16::from_be_bytes([size[0], size[1]]) as usize,
+            None => return Err(Error::TruncatedPacket { nal_type: nal_type }),
+        };
         offset += 2;
 
-        if size == 0 || offset + size > payload.len() {
-            break;
+        if size == 0 {
+            return Err(Error::InvalidAggregationUnit { nal_type: nal_type });
         }
-
-        let nal_unit = add_prefix_to_nal_unit(&payload[offset..offset + size]);
-        nal_units.push(nal_unit);
+        let nal_unit = aggregation_units
+            .get(offset..offset + size)
+            .ok_or(Error::TruncatedPacket { nal_type: nal_type })?;
+        nal_units.push(add_prefix_to_nal_unit(nal_unit));
 
         offset += size;
     }
 
-    Some(nal_units)
+    if nal_units.is_empty() {
+        return Err(Error::InvalidAggregationUnit { nal_type: nal_type });
+    }
+    Ok(nal_units)
 }
 
-fn convert_fu_a_to_nal_units(payload: &[u8], nal_buffer: &mut Vec<u8>) -> Option<Vec<Vec<u8>>> {
-    if payload.len() < 2 {
-        return None;
+fn convert_mtap_to_nal_units(
+    nal_type: u8,
+    payload: &[u8],
+    timestamp_offset_size: usize,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let mut nal_units = Vec::new();
+
+    // skip the NAL header and the decoding order number base
+    let mut offset = 3;
+    if payload.len() < offset {
+        return Err(Error::TruncatedPacket { nal_type: nal_type });
     }
 
-    let nal_header = payload[0];
-    let fu_header = payload[1];
+    // each unit is a size, a decoding order number difference, a timestamp offset and a NAL unit,
+    // where the size covers everything after itself
+    let unit_header_size = 1 + timestamp_offset_size;
+    while offset < payload.len() {
+        let size = match payload.get(offset..offset + 2) {
+            Some(size) => u16::from_be_bytes([size[0], size[1]]) as usize,
+            None => return Err(Error::TruncatedPacket { nal_type: nal_type }),
+        };
+        offset += 2;
+
+        if size <= unit_header_size {
+            return Err(Error::InvalidAggregationUnit { nal_type: nal_type });
+        }
+        let unit = payload
+            .get(offset..offset + size)
+            .ok_or(Error::TruncatedPacket { nal_type: nal_type })?;
+        nal_units.push(add_prefix_to_nal_unit(&unit[unit_header_size..]));
 
-    let start = fu_header & 0x80 != 0;
-    if start {
-        let reconstructed_nal_header = (nal_header & 0xE0) | (fu_header & 0x1F);
-        nal_buffer.clear();
-        nal_buffer.push(reconstructed_nal_header);
+        offset += size;
     }
 
-    nal_buffer.extend_from_slice(&payload[2..]);
-
-    let end = fu_header & 0x40 != 0;
-    if end {
-        Some(vec![add_prefix_to_nal_unit(&nal_buffer)])
-    } else {
-        None
+    if nal_units.is_empty() {
+        return Err(Error::InvalidAggregationUnit { nal_type: nal_type });
     }
+    Ok(nal_units)
 }
 
 fn add_prefix_to_nal_unit(slice: &[u8]) -> Vec<u8> {
@@ -99,11 +256,411 @@ fn add_prefix_to_nal_unit(slice: &[u8]) -> Vec<u8> {
 }
 
 pub(crate) fn get_prefix_code(nal_unit: &[u8]) -> Result<NalType, Error> {
-    if nal_unit.starts_with(&[0, 0, 1]) {
-        Ok(NalType::from(nal_unit[3] & 0x1F))
-    } else if nal_unit.starts_with(&[0, 0, 0, 1]) {
-        Ok(NalType::from(nal_unit[4] & 0x1F))
+    if nal_unit.starts_with(&[0, 0, 1]) && nal_unit.len() > 3 {
+        Ok(NalType::from(nal_unit[3] & NAL_TYPE_MASK))
+    } else if nal_unit.starts_with(&[0, 0, 0, 1]) && nal_unit.len() > 4 {
+        Ok(NalType::from(nal_unit[4] & NAL_TYPE_MASK))
     } else {
         Err(Error::MalformedNalUnit)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 4] = [0x67, 0x42, 0x00, 0x1F];
+    const PPS: [u8; 3] = [0x68, 0xCE, 0x3C];
+    const IDR: [u8; 5] = [0x65, 0x88, 0x84, 0x00, 0x33];
+
+    fn prefixed(nal_unit: &[u8]) -> Vec<u8> {
+        [&NAL_PREFIX_CODE[..], nal_unit].concat()
+    }
+
+    fn stap(nal_header: u8, nal_units: &[&[u8]]) -> Vec<u8> {
+        let mut payload = vec![nal_header];
+        for nal_unit in nal_units {
+            payload.extend_from_slice(&(nal_unit.len() as u16).to_be_bytes());
+            payload.extend_from_slice(nal_unit);
+        }
+        payload
+    }
+
+    fn fu_a(nal_unit: &[u8], start: bool, end: bool, fragment: &[u8]) -> Vec<u8> {
+        let fu_indicator = (nal_unit[0] & (FORBIDDEN_BIT_MASK | NRI_MASK)) | 28;
+        let mut fu_header = nal_unit[0] & NAL_TYPE_MASK;
+        if start {
+            fu_header |= FU_START_MASK;
+        }
+        if end {
+            fu_header |= FU_END_MASK;
+        }
+        [&[fu_indicator, fu_header][..], fragment].concat()
+    }
+
+    fn fragments(nal_unit: &[u8], size: usize) -> Vec<Vec<u8>> {
+        let chunks = nal_unit[1..].chunks(size).collect::<Vec<_>>();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| fu_a(nal_unit, i == 0, i == chunks.len() - 1, chunk))
+            .collect()
+    }
+
+    #[test]
+    fn single_nal_unit() {
+        let mut depacketizer = H264Depacketizer::new();
+        let nal_units = depacketizer.depacketize(0, &IDR).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn empty_payload() {
+        let mut depacketizer = H264Depacketizer::new();
+        assert!(matches!(
+            depacketizer.depacketize(0, &[]),
+            Err(Error::EmptyPayload)
+        ));
+    }
+
+    #[test]
+    fn forbidden_bit() {
+        let mut depacketizer = H264Depacketizer::new();
+        assert!(matches!(
+            depacketizer.depacketize(0, &[0x80 | IDR[0], 0x88]),
+            Err(Error::ForbiddenBitSet)
+        ));
+    }
+
+    #[test]
+    fn reserved_nal_types() {
+        let mut depacketizer = H264Depacketizer::new();
+        for nal_type in [0, 30, 31] {
+            assert!(matches!(
+                depacketizer.depacketize(0, &[0x60 | nal_type, 0x00]),
+                Err(Error::UnsupportedNalType { nal_type: t }) if t == nal_type
+            ));
+        }
+    }
+
+    #[test]
+    fn stap_a() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = stap(0x78, &[&SPS, &PPS, &IDR]);
+        let nal_units = depacketizer.depacketize(0, &payload).unwrap();
+        assert_eq!(
+            nal_units,
+            vec![prefixed(&SPS), prefixed(&PPS), prefixed(&IDR)]
+        );
+    }
+
+    #[test]
+    fn stap_a_truncated_size() {
+        let mut depacketizer = H264Depacketizer::new();
+        let mut payload = stap(0x78, &[&SPS]);
+        payload.push(0x00);
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::TruncatedPacket { nal_type: 24 })
+        ));
+    }
+
+    #[test]
+    fn stap_a_truncated_nal_unit() {
+        let mut depacketizer = H264Depacketizer::new();
+        let mut payload = stap(0x78, &[&SPS, &PPS]);
+        payload.pop();
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::TruncatedPacket { nal_type: 24 })
+        ));
+    }
+
+    #[test]
+    fn stap_a_zero_size() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = [0x78, 0x00, 0x00, 0x67];
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::InvalidAggregationUnit { nal_type: 24 })
+        ));
+    }
+
+    #[test]
+    fn stap_a_without_units() {
+        let mut depacketizer = H264Depacketizer::new();
+        assert!(matches!(
+            depacketizer.depacketize(0, &[0x78]),
+            Err(Error::InvalidAggregationUnit { nal_type: 24 })
+        ));
+    }
+
+    #[test]
+    fn stap_b() {
+        let mut depacketizer = H264Depacketizer::new();
+        let units = stap(0x79, &[&SPS, &PPS]);
+        let payload = [&[units[0], 0x12, 0x34][..], &units[1..]].concat();
+        let nal_units = depacketizer.depacketize(0, &payload).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&SPS), prefixed(&PPS)]);
+    }
+
+    #[test]
+    fn stap_b_missing_decoding_order_number() {
+        let mut depacketizer = H264Depacketizer::new();
+        assert!(matches!(
+            depacketizer.depacketize(0, &[0x79, 0x00]),
+            Err(Error::TruncatedPacket { nal_type: 25 })
+        ));
+    }
+
+    fn mtap(nal_header: u8, timestamp_offset_size: usize, nal_units: &[&[u8]]) -> Vec<u8> {
+        let mut payload = vec![nal_header, 0x00, 0x10];
+        for (i, nal_unit) in nal_units.iter().enumerate() {
+            let size = 1 + timestamp_offset_size + nal_unit.len();
+            payload.extend_from_slice(&(size as u16).to_be_bytes());
+            payload.push(i as u8);
+            payload.extend(std::iter::repeat_n(0xAB, timestamp_offset_size));
+            payload.extend_from_slice(nal_unit);
+        }
+        payload
+    }
+
+    #[test]
+    fn mtap16() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = mtap(0x7A, 2, &[&SPS, &IDR]);
+        let nal_units = depacketizer.depacketize(0, &payload).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&SPS), prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn mtap24() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = mtap(0x7B, 3, &[&PPS, &IDR]);
+        let nal_units = depacketizer.depacketize(0, &payload).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&PPS), prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn mtap16_unit_without_nal_unit() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = [0x7A, 0x00, 0x10, 0x00, 0x03, 0x00, 0xAB, 0xAB];
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::InvalidAggregationUnit { nal_type: 26 })
+        ));
+    }
+
+    #[test]
+    fn mtap24_truncated() {
+        let mut depacketizer = H264Depacketizer::new();
+        let mut payload = mtap(0x7B, 3, &[&IDR]);
+        payload.truncate(payload.len() - 2);
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::TruncatedPacket { nal_type: 27 })
+        ));
+    }
+
+    #[test]
+    fn fu_a_two_fragments() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+        assert_eq!(packets.len(), 2);
+
+        assert!(
+            depacketizer
+                .depacketize(10, &packets[0])
+                .unwrap()
+                .is_empty()
+        );
+        let nal_units = depacketizer.depacketize(11, &packets[1]).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn fu_a_many_fragments() {
+        let mut depacketizer = H264Depacketizer::new();
+        let nal_unit = [&[0x41][..], &(0..=255).collect::<Vec<u8>>()].concat();
+        let packets = fragments(&nal_unit, 10);
+
+        let mut nal_units = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            nal_units.extend(depacketizer.depacketize(i as u16, packet).unwrap());
+        }
+        assert_eq!(nal_units, vec![prefixed(&nal_unit)]);
+    }
+
+    #[test]
+    fn fu_a_sequence_number_wraps_around() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 1);
+        assert_eq!(packets.len(), 4);
+
+        let mut nal_units = Vec::new();
+        for (sequence_number, packet) in [65534, 65535, 0, 1].into_iter().zip(&packets) {
+            nal_units.extend(depacketizer.depacketize(sequence_number, packet).unwrap());
+        }
+        assert_eq!(nal_units, vec![prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn fu_a_missing_fragment() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 1);
+
+        depacketizer.depacketize(0, &packets[0]).unwrap();
+        depacketizer.depacketize(1, &packets[1]).unwrap();
+        assert!(matches!(
+            depacketizer.depacketize(3, &packets[3]),
+            Err(Error::MissingFragment {
+                expected: 2,
+                received: 3
+            })
+        ));
+
+        // the broken NAL unit is dropped entirely
+        assert!(matches!(
+            depacketizer.depacketize(4, &packets[3]),
+            Err(Error::UnexpectedFragment)
+        ));
+    }
+
+    #[test]
+    fn fu_a_missing_start() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+        assert!(matches!(
+            depacketizer.depacketize(1, &packets[1]),
+            Err(Error::UnexpectedFragment)
+        ));
+    }
+
+    #[test]
+    fn fu_a_missing_end() {
+        let mut depacketizer = H264Depacketizer::new();
+        let idr = fragments(&IDR, 2);
+        let non_idr = fragments(&[0x41, 0x9A, 0x00, 0x11], 2);
+
+        depacketizer.depacketize(0, &idr[0]).unwrap();
+        assert!(matches!(
+            depacketizer.depacketize(1, &non_idr[0]),
+            Err(Error::IncompleteFragment)
+        ));
+
+        // the new NAL unit is still reassembled
+        let nal_units = depacketizer.depacketize(2, &non_idr[1]).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&[0x41, 0x9A, 0x00, 0x11])]);
+    }
+
+    #[test]
+    fn fu_a_fragment_of_another_nal_unit() {
+        let mut depacketizer = H264Depacketizer::new();
+        let idr = fragments(&IDR, 2);
+        let non_idr = fragments(&[0x41, 0x9A, 0x00, 0x11], 2);
+
+        depacketizer.depacketize(0, &idr[0]).unwrap();
+        assert!(matches!(
+            depacketizer.depacketize(1, &non_idr[1]),
+            Err(Error::FragmentMismatch {
+                expected: 5,
+                received: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn fu_a_nri_mismatch() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+        let mut continuation = packets[1].clone();
+        continuation[0] &= !NRI_MASK;
+
+        depacketizer.depacketize(0, &packets[0]).unwrap();
+        assert!(matches!(
+            depacketizer.depacketize(1, &continuation),
+            Err(Error::FragmentMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn fu_a_start_and_end() {
+        let mut depacketizer = H264Depacketizer::new();
+        let payload = fu_a(&IDR, true, true, &IDR[1..]);
+        assert!(matches!(
+            depacketizer.depacketize(0, &payload),
+            Err(Error::InvalidFragment)
+        ));
+    }
+
+    #[test]
+    fn fu_a_truncated() {
+        let mut depacketizer = H264Depacketizer::new();
+        assert!(matches!(
+            depacketizer.depacketize(0, &[0x7C]),
+            Err(Error::TruncatedPacket { nal_type: 28 })
+        ));
+        assert!(matches!(
+            depacketizer.depacketize(0, &[0x7C, 0x85]),
+            Err(Error::TruncatedPacket { nal_type: 28 })
+        ));
+    }
+
+    #[test]
+    fn fu_b_followed_by_fu_a() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+        let mut fu_b = packets[0].clone();
+        fu_b[0] = (fu_b[0] & !NAL_TYPE_MASK) | 29;
+        fu_b.splice(2..2, [0x00, 0x07]);
+
+        assert!(depacketizer.depacketize(0, &fu_b).unwrap().is_empty());
+        let nal_units = depacketizer.depacketize(1, &packets[1]).unwrap();
+        assert_eq!(nal_units, vec![prefixed(&IDR)]);
+    }
+
+    #[test]
+    fn fu_b_continuation() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+        let mut fu_b = packets[1].clone();
+        fu_b[0] = (fu_b[0] & !NAL_TYPE_MASK) | 29;
+        fu_b.splice(2..2, [0x00, 0x07]);
+
+        depacketizer.depacketize(0, &packets[0]).unwrap();
+        assert!(matches!(
+            depacketizer.depacketize(1, &fu_b),
+            Err(Error::InvalidFragment)
+        ));
+    }
+
+    #[test]
+    fn reset_discards_fragment() {
+        let mut depacketizer = H264Depacketizer::new();
+        let packets = fragments(&IDR, 2);
+
+        depacketizer.depacketize(0, &packets[0]).unwrap();
+        depacketizer.reset();
+        assert!(matches!(
+            depacketizer.depacketize(1, &packets[1]),
+            Err(Error::UnexpectedFragment)
+        ));
+    }
+
+    #[test]
+    fn prefix_code() {
+        assert!(matches!(get_prefix_code(&prefixed(&IDR)), Ok(NalType::IDR)));
+        assert!(matches!(
+            get_prefix_code(&[0, 0, 1, SPS[0]]),
+            Ok(NalType::SPS)
+        ));
+        assert!(matches!(
+            get_prefix_code(&[0, 0, 0, 1]),
+            Err(Error::MalformedNalUnit)
+        ));
+        assert!(matches!(
+            get_prefix_code(&IDR),
+            Err(Error::MalformedNalUnit)
+        ));
+    }
+}
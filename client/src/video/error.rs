@@ -4,6 +4,17 @@ pub enum Error {
     OpenH264 { error: openh264::Error },
     Decoding,
     MalformedNalUnit,
+    AwaitingKeyframe,
+    EmptyPayload,
+    ForbiddenBitSet,
+    UnsupportedNalType { nal_type: u8 },
+    TruncatedPacket { nal_type: u8 },
+    InvalidAggregationUnit { nal_type: u8 },
+    InvalidFragment,
+    UnexpectedFragment,
+    IncompleteFragment,
+    MissingFragment { expected: u16, received: u16 },
+    FragmentMismatch { expected: u8, received: u8 },
 }
 
 impl std::fmt::Display for Error {
@@ -13,6 +24,31 @@ impl std::fmt::Display for Error {
             Error::OpenH264 { error } => write!(f, "{}", error),
             Error::Decoding => write!(f, "nothing to decode"),
             Error::MalformedNalUnit => write!(f, "malformed NAL unit"),
+            Error::AwaitingKeyframe => write!(f, "waiting for keyframe"),
+            Error::EmptyPayload => write!(f, "empty RTP payload"),
+            Error::ForbiddenBitSet => write!(f, "forbidden bit set in NAL header"),
+            Error::UnsupportedNalType { nal_type } => {
+                write!(f, "unsupported NAL unit type {}", nal_type)
+            }
+            Error::TruncatedPacket { nal_type } => {
+                write!(f, "truncated packet of NAL unit type {}", nal_type)
+            }
+            Error::InvalidAggregationUnit { nal_type } => {
+                write!(f, "invalid aggregation unit in NAL unit type {}", nal_type)
+            }
+            Error::InvalidFragment => write!(f, "invalid fragmentation unit"),
+            Error::UnexpectedFragment => write!(f, "fragment without a start fragment"),
+            Error::IncompleteFragment => write!(f, "fragmented NAL unit is missing its end"),
+            Error::MissingFragment { expected, received } => write!(
+                f,
+                "missing fragment: expected sequence number {}, received {}",
+                expected, received
+            ),
+            Error::FragmentMismatch { expected, received } => write!(
+                f,
+                "fragment of NAL unit type {} while reassembling type {}",
+                received, expected
+            ),
         }
     }
 }
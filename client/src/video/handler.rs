@@ -9,17 +9,21 @@ use tracing::info;
 use crate::video::{
     encoding::{NalType, get_prefix_code},
     error::Error,
+    latency::LatencyBreakdown,
+    pool::SharedFrame,
 };
 
 pub trait VideoHandler {
-    fn receive_stream(&mut self, stream: &Vec<u8>) -> Result<(usize, usize), Error>;
+    type Stream;
+
+    fn receive_stream(&mut self, stream: Self::Stream) -> Result<(usize, usize), Error>;
     fn rgb_buffer(&self) -> &[u8];
 }
 
 pub struct LocalVideoHandler {
     width: usize,
     height: usize,
-    rgb_buffer: Vec<u8>,
+    frame: Option<SharedFrame>,
 }
 
 impl LocalVideoHandler {
@@ -42,33 +46,48 @@ impl LocalVideoHandler {
                 (0, 0)
             }
         };
-        let rgb_buffer = vec![0; width * height * 3];
 
         Ok(Self {
             width: width,
             height: height,
-            rgb_buffer: rgb_buffer,
+            frame: None,
         })
     }
 }
 
 impl VideoHandler for LocalVideoHandler {
-    fn receive_stream(&mut self, stream: &Vec<u8>) -> Result<(usize, usize), Error> {
-        self.rgb_buffer.copy_from_slice(&stream);
+    type Stream = SharedFrame;
+
+    // holds on to the captured frame instead of copying it
+    fn receive_stream(&mut self, stream: SharedFrame) -> Result<(usize, usize), Error> {
+        if stream.len() != self.width * self.height * 3 {
+            return Err(Error::Decoding);
+        }
+        self.frame = Some(stream);
         Ok((self.width, self.height))
     }
 
     fn rgb_buffer(&self) -> &[u8] {
-        &self.rgb_buffer
+        match &self.frame {
+            Some(frame) => frame,
+            None => &[],
+        }
     }
 }
 
+pub struct PeerFrame {
+    pub data: Vec<u8>,
+    // capture, encode and network delay, when the sender provided timestamps
+    pub latency: Option<LatencyBreakdown>,
+}
+
 pub struct PeerVideoHandler {
     h264_decoder: openh264::decoder::Decoder,
     sps: Option<Vec<u8>>,
     pps: Option<Vec<u8>>,
     frame_buffer: Vec<u8>,
     rgb_buffer: Vec<u8>,
+    awaiting_keyframe: bool,
 }
 
 impl PeerVideoHandler {
@@ -82,6 +101,7 @@ impl PeerVideoHandler {
             pps: None,
             frame_buffer: Vec::new(),
             rgb_buffer: Vec::new(),
+            awaiting_keyframe: true,
         })
     }
 
@@ -97,10 +117,23 @@ impl PeerVideoHandler {
         let decoded = self
             .h264_decoder
             .decode(&self.frame_buffer)
-            .map_err(|e| Error::OpenH264 { error: e })?
-            .ok_or_else(|| Error::Decoding)?;
+            .map_err(|e| Error::OpenH264 { error: e });
         self.frame_buffer.clear();
 
+        // keep showing the last good frame and skip everything up to the next keyframe,
+        // since later frames would only reference the broken one
+        let decoded = match decoded {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => {
+                self.awaiting_keyframe = true;
+                return Err(Error::Decoding);
+            }
+            Err(e) => {
+                self.awaiting_keyframe = true;
+                return Err(e);
+            }
+        };
+
         let (width, height) = decoded.dimensions();
         let need_resize = self.rgb_buffer.len() != width * height * 3;
         if need_resize {
@@ -112,7 +145,15 @@ impl PeerVideoHandler {
 }
 
 impl VideoHandler for PeerVideoHandler {
-    fn receive_stream(&mut self, stream: &Vec<u8>) -> Result<(usize, usize), Error> {
+    type Stream = Vec<u8>;
+
+    // an empty stream signals that frames were lost on the way
+    fn receive_stream(&mut self, stream: Vec<u8>) -> Result<(usize, usize), Error> {
+        if stream.is_empty() {
+            self.awaiting_keyframe = true;
+            return Err(Error::AwaitingKeyframe);
+        }
+
         let mut contains_idr = false;
         for nal_unit in openh264::nal_units(&stream) {
             let nal_type = get_prefix_code(nal_unit)?;
@@ -126,6 +167,9 @@ impl VideoHandler for PeerVideoHandler {
 
         if contains_idr {
             self.init_frame_buffer();
+            self.awaiting_keyframe = false;
+        } else if self.awaiting_keyframe {
+            return Err(Error::AwaitingKeyframe);
         }
         self.frame_buffer.extend_from_slice(&stream);
 
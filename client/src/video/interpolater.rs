@@ -44,6 +44,10 @@ impl BilinearInterpolater {
         }
     }
 
+    pub(crate) fn display_dimensions(&self) -> (usize, usize) {
+        (self.display_width as usize, self.display_height as usize)
+    }
+
     pub(crate) fn update_weights_if_needed(&mut self, input_width: usize, input_height: usize) {
         if input_width == self.input_width && input_height == self.input_height {
             return;
@@ -0,0 +1,423 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use webrtc::rtp::packet::Packet;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReceiveStats {
+    pub received: u64,
+    pub lost: u64,
+    pub late: u64,
+    pub duplicates: u64,
+    pub dropped_frames: u64,
+    pub jitter: Duration,
+}
+
+impl ReceiveStats {
+    pub fn loss_fraction(&self) -> f32 {
+        let expected = self.received + self.lost;
+        if expected == 0 {
+            return 0.0;
+        }
+
+        self.lost as f32 / expected as f32
+    }
+}
+
+pub enum Playout {
+    Frame(Vec<Packet>),
+    Dropped,
+}
+
+// Reorders RTP packets by sequence number and only releases complete access units,
+// i.e. a contiguous run of packets ending with the marker bit. When a gap can't be
+// filled in time, the rest of the affected access unit is discarded, up to its marker or
+// the first packet of another access unit, in case the marker was what got lost.
+pub struct JitterBuffer {
+    clock_rate: f64,
+    epoch: Instant,
+    packets: BTreeMap<u64, (Packet, Instant)>,
+    highest_sequence: Option<u64>,
+    next_sequence: Option<u64>,
+    // the RTP timestamp of the access unit being discarded
+    discarding: Option<u32>,
+    last_transit: Option<f64>,
+    jitter: f64,
+    stats: ReceiveStats,
+}
+
+impl JitterBuffer {
+    pub const MAX_DELAY: Duration = Duration::from_millis(100);
+    const MAX_PACKETS: usize = 1024;
+
+    // extended sequence numbers start one cycle in so that they never underflow
+    const SEQUENCE_CYCLE: u64 = 1 << 16;
+
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate: clock_rate as f64,
+            epoch: Instant::now(),
+            packets: BTreeMap::new(),
+            highest_sequence: None,
+            next_sequence: None,
+            discarding: None,
+            last_transit: None,
+            jitter: 0.0,
+            stats: ReceiveStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ReceiveStats {
+        let mut stats = self.stats;
+        stats.jitter = Duration::from_secs_f64(self.jitter / self.clock_rate);
+        stats
+    }
+
+    fn extend_sequence(&self, sequence_number: u16) -> u64 {
+        let highest = match self.highest_sequence {
+            Some(highest) => highest,
+            None => return Self::SEQUENCE_CYCLE + sequence_number as u64,
+        };
+
+        let cycle = highest & !(Self::SEQUENCE_CYCLE - 1);
+        [
+            cycle - Self::SEQUENCE_CYCLE,
+            cycle,
+            cycle + Self::SEQUENCE_CYCLE,
+        ]
+        .into_iter()
+        .map(|cycle| cycle + sequence_number as u64)
+        .min_by_key(|candidate| candidate.abs_diff(highest))
+        .unwrap_or(cycle + sequence_number as u64)
+    }
+
+    fn update_jitter(&mut self, packet: &Packet, arrival: Instant) {
+        // https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8
+        let arrival = arrival.duration_since(self.epoch).as_secs_f64() * self.clock_rate;
+        let transit = arrival - packet.header.timestamp as f64;
+        if let Some(last_transit) = self.last_transit {
+            let difference = (transit - last_transit).abs();
+            // ignore the jump when the RTP timestamp wraps around
+            if difference < self.clock_rate {
+                self.jitter += (difference - self.jitter) / 16.0;
+            }
+        }
+        self.last_transit = Some(transit);
+    }
+
+    pub fn push(&mut self, packet: Packet, arrival: Instant) {
+        let sequence = self.extend_sequence(packet.header.sequence_number);
+
+        if self.next_sequence.is_some_and(|next| sequence < next) {
+            self.stats.late += 1;
+            return;
+        }
+        if self.packets.contains_key(&sequence) {
+            self.stats.duplicates += 1;
+            return;
+        }
+
+        self.stats.received += 1;
+        self.update_jitter(&packet, arrival);
+
+        if self
+            .highest_sequence
+            .is_none_or(|highest| sequence > highest)
+        {
+            self.highest_sequence = Some(sequence);
+        }
+        if self.next_sequence.is_none() {
+            self.next_sequence = Some(sequence);
+        }
+        self.packets.insert(sequence, (packet, arrival));
+    }
+
+    fn should_stop_waiting(&self, now: Instant) -> bool {
+        if self.packets.len() > Self::MAX_PACKETS {
+            return true;
+        }
+
+        self.packets
+            .values()
+            .next()
+            .is_some_and(|(_, arrival)| now.duration_since(*arrival) > Self::MAX_DELAY)
+    }
+
+    fn skip_gap(&mut self, next: u64) -> bool {
+        let first_buffered = match self.packets.keys().next() {
+            Some(first_buffered) => *first_buffered,
+            None => return false,
+        };
+
+        self.stats.lost += first_buffered - next;
+        self.next_sequence = Some(first_buffered);
+        true
+    }
+
+    // the sequence number of the marker that completes the access unit starting at next
+    fn complete_from(&self, next: u64) -> Option<u64> {
+        let mut end = next;
+        while let Some((packet, _)) = self.packets.get(&end) {
+            if packet.header.marker {
+                return Some(end);
+            }
+            end += 1;
+        }
+        None
+    }
+
+    pub fn pop(&mut self, now: Instant) -> Option<Playout> {
+        loop {
+            let next = self.next_sequence?;
+
+            if let Some(timestamp) = self.discarding {
+                match self.packets.get(&next) {
+                    Some((packet, _)) if packet.header.timestamp != timestamp => {
+                        self.discarding = None;
+                    }
+                    Some((packet, _)) => {
+                        if packet.header.marker {
+                            self.discarding = None;
+                        }
+                        self.packets.remove(&next);
+                        self.next_sequence = Some(next + 1);
+                    }
+                    None => {
+                        if !self.skip_gap(next) {
+                            return None;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if let Some(end) = self.complete_from(next) {
+                let packets = (next..=end)
+                    .filter_map(|sequence| self.packets.remove(&sequence))
+                    .map(|(packet, _)| packet)
+                    .collect();
+                self.next_sequence = Some(end + 1);
+                return Some(Playout::Frame(packets));
+            }
+
+            if self.packets.is_empty() || !self.should_stop_waiting(now) {
+                return None;
+            }
+
+            // when the gap comes first, the access unit after it may have lost its start, so it's
+            // dropped as well, whether or not its marker made it
+            if !self.packets.contains_key(&next) {
+                self.skip_gap(next);
+            }
+            if let Some(next) = self.next_sequence {
+                self.discarding = self
+                    .packets
+                    .get(&next)
+                    .map(|(packet, _)| packet.header.timestamp);
+            }
+            self.stats.dropped_frames += 1;
+            return Some(Playout::Dropped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use webrtc::rtp::header::Header;
+
+    fn packet(sequence_number: u16, timestamp: u32, marker: bool) -> Packet {
+        Packet {
+            header: Header {
+                sequence_number: sequence_number,
+                timestamp: timestamp,
+                marker: marker,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn sequence_numbers(playout: Option<Playout>) -> Option<Vec<u16>> {
+        match playout {
+            Some(Playout::Frame(packets)) => Some(
+                packets
+                    .iter()
+                    .map(|packet| packet.header.sequence_number)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn is_dropped(playout: Option<Playout>) -> bool {
+        matches!(playout, Some(Playout::Dropped))
+    }
+
+    #[test]
+    fn complete_frames() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(1, 0, false), now);
+        assert!(jitter_buffer.pop(now).is_none());
+
+        jitter_buffer.push(packet(2, 0, true), now);
+        jitter_buffer.push(packet(3, 3000, true), now);
+        assert_eq!(sequence_numbers(jitter_buffer.pop(now)), Some(vec![1, 2]));
+        assert_eq!(sequence_numbers(jitter_buffer.pop(now)), Some(vec![3]));
+        assert!(jitter_buffer.pop(now).is_none());
+    }
+
+    #[test]
+    fn sequence_wraparound() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        for (sequence_number, timestamp, marker) in [
+            (65534, 0, false),
+            (65535, 0, true),
+            (0, 3000, false),
+            (1, 3000, true),
+        ] {
+            jitter_buffer.push(packet(sequence_number, timestamp, marker), now);
+        }
+
+        assert_eq!(
+            sequence_numbers(jitter_buffer.pop(now)),
+            Some(vec![65534, 65535])
+        );
+        assert_eq!(sequence_numbers(jitter_buffer.pop(now)), Some(vec![0, 1]));
+        assert_eq!(jitter_buffer.stats().lost, 0);
+    }
+
+    #[test]
+    fn wraparound_out_of_order() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(65535, 0, false), now);
+        jitter_buffer.push(packet(1, 0, true), now);
+        jitter_buffer.push(packet(0, 0, false), now);
+
+        assert_eq!(
+            sequence_numbers(jitter_buffer.pop(now)),
+            Some(vec![65535, 0, 1])
+        );
+    }
+
+    #[test]
+    fn reordering_within_max_delay() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(10, 0, false), now);
+        jitter_buffer.push(packet(12, 0, true), now);
+        assert!(
+            jitter_buffer
+                .pop(now + JitterBuffer::MAX_DELAY / 2)
+                .is_none()
+        );
+
+        jitter_buffer.push(packet(11, 0, false), now + JitterBuffer::MAX_DELAY / 2);
+        assert_eq!(
+            sequence_numbers(jitter_buffer.pop(now + JitterBuffer::MAX_DELAY / 2)),
+            Some(vec![10, 11, 12])
+        );
+        let stats = jitter_buffer.stats();
+        assert_eq!((stats.lost, stats.dropped_frames), (0, 0));
+    }
+
+    #[test]
+    fn late_and_duplicate_packets() {
+        let now = Instant::now();
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(1, 0, true), now);
+        jitter_buffer.push(packet(2, 3000, false), now);
+        jitter_buffer.push(packet(2, 3000, false), now);
+        assert!(jitter_buffer.pop(now).is_some());
+
+        jitter_buffer.push(packet(1, 0, true), now);
+        let stats = jitter_buffer.stats();
+        assert_eq!((stats.received, stats.duplicates, stats.late), (2, 1, 1));
+    }
+
+    #[test]
+    fn lost_middle_packet() {
+        let now = Instant::now();
+        let later = now + JitterBuffer::MAX_DELAY + Duration::from_millis(1);
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        for (sequence_number, timestamp, marker) in [
+            (1, 0, false),
+            (3, 0, true),
+            (4, 3000, false),
+            (5, 3000, true),
+        ] {
+            jitter_buffer.push(packet(sequence_number, timestamp, marker), now);
+        }
+        assert!(jitter_buffer.pop(now).is_none());
+
+        assert!(is_dropped(jitter_buffer.pop(later)));
+        assert_eq!(sequence_numbers(jitter_buffer.pop(later)), Some(vec![4, 5]));
+        let stats = jitter_buffer.stats();
+        assert_eq!((stats.lost, stats.dropped_frames), (1, 1));
+    }
+
+    #[test]
+    fn lost_marker_packet() {
+        let now = Instant::now();
+        let later = now + JitterBuffer::MAX_DELAY + Duration::from_millis(1);
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        for (sequence_number, timestamp, marker) in [
+            (1, 0, false),
+            (2, 0, false),
+            (4, 3000, false),
+            (5, 3000, true),
+        ] {
+            jitter_buffer.push(packet(sequence_number, timestamp, marker), now);
+        }
+
+        // the access unit after the lost marker is complete, so only the first one goes
+        assert!(is_dropped(jitter_buffer.pop(later)));
+        assert_eq!(sequence_numbers(jitter_buffer.pop(later)), Some(vec![4, 5]));
+        let stats = jitter_buffer.stats();
+        assert_eq!((stats.lost, stats.dropped_frames), (1, 1));
+    }
+
+    #[test]
+    fn lost_first_packet() {
+        let now = Instant::now();
+        let later = now + JitterBuffer::MAX_DELAY + Duration::from_millis(1);
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(1, 0, true), now);
+        assert!(jitter_buffer.pop(now).is_some());
+
+        // 2 started the access unit that 3 and 4 belong to
+        for (sequence_number, timestamp, marker) in
+            [(3, 3000, false), (4, 3000, true), (5, 6000, true)]
+        {
+            jitter_buffer.push(packet(sequence_number, timestamp, marker), now);
+        }
+        assert!(jitter_buffer.pop(now).is_none());
+
+        assert!(is_dropped(jitter_buffer.pop(later)));
+        assert_eq!(sequence_numbers(jitter_buffer.pop(later)), Some(vec![5]));
+        assert!(jitter_buffer.pop(later).is_none());
+    }
+
+    #[test]
+    fn discarding_waits_for_the_rest_of_the_access_unit() {
+        let now = Instant::now();
+        let later = now + JitterBuffer::MAX_DELAY + Duration::from_millis(1);
+        let mut jitter_buffer = JitterBuffer::new(90_000);
+        jitter_buffer.push(packet(1, 0, false), now);
+        jitter_buffer.push(packet(3, 0, false), now);
+
+        assert!(is_dropped(jitter_buffer.pop(later)));
+        assert!(jitter_buffer.pop(later).is_none());
+
+        // the rest of the discarded access unit, then a complete one
+        jitter_buffer.push(packet(4, 0, true), later);
+        jitter_buffer.push(packet(5, 3000, true), later);
+        assert_eq!(sequence_numbers(jitter_buffer.pop(later)), Some(vec![5]));
+    }
+}
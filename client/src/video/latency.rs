@@ -0,0 +1,249 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use webrtc::{
+    rtcp::receiver_report::ReceiverReport,
+    rtp::{extension::HeaderExtension, packet::Packet},
+    util::{Marshal, MarshalSize},
+};
+
+pub const ABS_CAPTURE_TIME_URI: &str =
+    "http://www.webrtc.org/experiments/rtp-hdrext/abs-capture-time";
+pub const FRAME_TIMING_URI: &str = "urn:terminally-online:rtp-hdrext:frame-timing";
+
+// seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+fn to_ntp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+fn from_ntp(ntp: u64) -> SystemTime {
+    let seconds = (ntp >> 32).saturating_sub(NTP_UNIX_OFFSET);
+    let nanos = ((ntp & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+    UNIX_EPOCH + Duration::new(seconds, nanos as u32)
+}
+
+fn write_bytes(buf: &mut [u8], bytes: &[u8]) -> Result<usize, webrtc::util::Error> {
+    let target = buf
+        .get_mut(..bytes.len())
+        .ok_or(webrtc::util::Error::ErrBufferShort)?;
+    target.copy_from_slice(bytes);
+    Ok(bytes.len())
+}
+
+// https://webrtc.googlesource.com/src/+/refs/heads/main/docs/native-code/rtp-hdrext/abs-capture-time,
+// without the optional clock offset
+struct AbsCaptureTimeExtension {
+    capture_time: SystemTime,
+}
+
+impl MarshalSize for AbsCaptureTimeExtension {
+    fn marshal_size(&self) -> usize {
+        8
+    }
+}
+
+impl Marshal for AbsCaptureTimeExtension {
+    fn marshal_to(&self, buf: &mut [u8]) -> Result<usize, webrtc::util::Error> {
+        write_bytes(buf, &to_ntp(self.capture_time).to_be_bytes())
+    }
+}
+
+// How long the sender spent on a frame before handing it to the network, along with the
+// sender's view of the round trip time, which the receiver needs to estimate the clock offset
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTiming {
+    pub capture_time: SystemTime,
+    pub capture: Duration,
+    pub encode: Duration,
+    pub round_trip_time: Duration,
+}
+
+struct FrameTimingExtension {
+    capture: Duration,
+    encode: Duration,
+    round_trip_time: Duration,
+}
+
+fn to_micros(duration: Duration) -> u32 {
+    duration.as_micros().min(u32::MAX as u128) as u32
+}
+
+impl MarshalSize for FrameTimingExtension {
+    fn marshal_size(&self) -> usize {
+        12
+    }
+}
+
+impl Marshal for FrameTimingExtension {
+    fn marshal_to(&self, buf: &mut [u8]) -> Result<usize, webrtc::util::Error> {
+        let mut bytes = [0; 12];
+        bytes[0..4].copy_from_slice(&to_micros(self.capture).to_be_bytes());
+        bytes[4..8].copy_from_slice(&to_micros(self.encode).to_be_bytes());
+        bytes[8..12].copy_from_slice(&to_micros(self.round_trip_time).to_be_bytes());
+        write_bytes(buf, &bytes)
+    }
+}
+
+impl FrameTiming {
+    pub fn header_extensions(&self) -> [HeaderExtension; 2] {
+        [
+            HeaderExtension::Custom {
+                uri: ABS_CAPTURE_TIME_URI.into(),
+                extension: Box::new(AbsCaptureTimeExtension {
+                    capture_time: self.capture_time,
+                }),
+            },
+            HeaderExtension::Custom {
+                uri: FRAME_TIMING_URI.into(),
+                extension: Box::new(FrameTimingExtension {
+                    capture: self.capture,
+                    encode: self.encode,
+                    round_trip_time: self.round_trip_time,
+                }),
+            },
+        ]
+    }
+
+    // ids are the ones negotiated for the receiving track
+    pub fn from_packet(packet: &Packet, capture_time_id: u8, frame_timing_id: u8) -> Option<Self> {
+        let capture_time = packet.header.get_extension(capture_time_id)?;
+        let frame_timing = packet.header.get_extension(frame_timing_id)?;
+        if capture_time.len() < 8 || frame_timing.len() < 12 {
+            return None;
+        }
+
+        let micros = |offset: usize| {
+            let bytes = [
+                frame_timing[offset],
+                frame_timing[offset + 1],
+                frame_timing[offset + 2],
+                frame_timing[offset + 3],
+            ];
+            Duration::from_micros(u32::from_be_bytes(bytes) as u64)
+        };
+        let mut ntp = [0; 8];
+        ntp.copy_from_slice(&capture_time[..8]);
+
+        Some(Self {
+            capture_time: from_ntp(u64::from_be_bytes(ntp)),
+            capture: micros(0),
+            encode: micros(4),
+            round_trip_time: micros(8),
+        })
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1
+pub(crate) fn round_trip_time(
+    receiver_report: &ReceiverReport,
+    now: SystemTime,
+) -> Option<Duration> {
+    let now = (to_ntp(now) >> 16) as u32;
+    receiver_report
+        .reports
+        .iter()
+        .filter(|report| report.last_sender_report != 0)
+        .map(|report| {
+            let rtt = now
+                .wrapping_sub(report.last_sender_report)
+                .wrapping_sub(report.delay);
+            Duration::from_secs_f64(rtt as f64 / 65536.0)
+        })
+        // anything this large is a wrapped around or stale report
+        .filter(|rtt| *rtt < Duration::from_secs(10))
+        .min()
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LatencyBreakdown {
+    pub capture: Duration,
+    pub encode: Duration,
+    pub network: Duration,
+    pub decode: Duration,
+    pub render: Duration,
+}
+
+impl LatencyBreakdown {
+    pub fn total(&self) -> Duration {
+        self.capture + self.encode + self.network + self.decode + self.render
+    }
+}
+
+// Estimates how far our clock is ahead of the sender's. The smallest recent difference between
+// our receive time and their send time is taken to be the offset plus half of the round trip time.
+pub struct ClockOffsetEstimator {
+    samples: VecDeque<f64>,
+}
+
+impl ClockOffsetEstimator {
+    const WINDOW: usize = 300;
+
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(Self::WINDOW),
+        }
+    }
+
+    fn seconds_between(from: SystemTime, to: SystemTime) -> f64 {
+        match to.duration_since(from) {
+            Ok(duration) => duration.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        }
+    }
+
+    // network delay of a frame that was handed to the decoder at `received_at`
+    pub fn network_delay(&mut self, timing: &FrameTiming, received_at: SystemTime) -> Duration {
+        let sent_at = timing.capture_time + timing.capture + timing.encode;
+        let one_way = Self::seconds_between(sent_at, received_at);
+
+        if self.samples.len() == Self::WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(one_way);
+
+        let minimum = self.samples.iter().copied().fold(f64::INFINITY, f64::min);
+        let offset = minimum - timing.round_trip_time.as_secs_f64() / 2.0;
+        Duration::from_secs_f64((one_way - offset).max(0.0))
+    }
+}
+
+// Smooths per-frame samples so that the displayed numbers don't flicker
+pub struct LatencyMeter {
+    breakdown: Option<LatencyBreakdown>,
+}
+
+impl LatencyMeter {
+    const SMOOTHING: f64 = 1.0 / 8.0;
+
+    pub fn new() -> Self {
+        Self { breakdown: None }
+    }
+
+    pub fn record(&mut self, sample: LatencyBreakdown) {
+        let smooth = |previous: Duration, sample: Duration| {
+            previous.mul_f64(1.0 - Self::SMOOTHING) + sample.mul_f64(Self::SMOOTHING)
+        };
+
+        self.breakdown = Some(match self.breakdown {
+            Some(previous) => LatencyBreakdown {
+                capture: smooth(previous.capture, sample.capture),
+                encode: smooth(previous.encode, sample.encode),
+                network: smooth(previous.network, sample.network),
+                decode: smooth(previous.decode, sample.decode),
+                render: smooth(previous.render, sample.render),
+            },
+            None => sample,
+        });
+    }
+
+    pub fn breakdown(&self) -> Option<LatencyBreakdown> {
+        self.breakdown
+    }
+}
@@ -1,34 +1,58 @@
-use std::io::Write;
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
 
 use crossterm::{
     QueueableCommand,
     cursor::MoveTo,
-    style::{Color, PrintStyledContent, Stylize},
+    style::{Color, Print, PrintStyledContent, Stylize},
 };
 
 use crate::{
+    client::message::Renderer,
     layout::Drawable,
+    terminal::TerminalScreen,
     video::{
         error::Error,
         handler::{LocalVideoHandler, PeerVideoHandler, VideoHandler},
         interpolater::BilinearInterpolater,
+        latency::LatencyBreakdown,
     },
 };
 
+pub mod bitrate;
+pub mod channel;
 pub mod encoding;
 pub mod error;
 pub mod handler;
+pub mod jitter;
+pub mod latency;
+pub mod pool;
 pub mod webcam;
 
 mod interpolater;
+mod scaler;
 
 const UPPER_HALF_BLOCK: char = '▀';
+const RECOVERING_MARKER: &str = "⟳ recovering";
+const STOPPED_MARKER: &str = "■ stopped";
+pub const HAND_MARKER: &str = "✋";
+const REACTION_DURATION: Duration = Duration::from_secs(3);
 
 pub struct VideoPanel<T: VideoHandler> {
     x: u16,
     y: u16,
     width: u16,
     height: u16,
+    title: Option<String>,
+    recovering: bool,
+    stopped: bool,
+    latency: Option<LatencyBreakdown>,
+    // shown instead of the video while it's there
+    terminal: Option<TerminalScreen>,
+    reaction: Option<(String, Instant)>,
+    hand_raised: bool,
     video_handler: T,
     bilinear_interpolater: BilinearInterpolater,
 }
@@ -55,20 +79,49 @@ impl<T: VideoHandler> VideoPanel<T> {
     const PADDING: u16 = 1;
 
     fn new(x: u16, y: u16, width: u16, height: u16, video_handler: T) -> Result<Self, Error> {
-        let bilinear_interpolater =
-            BilinearInterpolater::new(width - 2 * (Self::PADDING + 1), (height - 2) * 2);
+        let (inner_width, inner_height) = Self::inner_size(width, height);
+        let bilinear_interpolater = BilinearInterpolater::new(inner_width, inner_height * 2);
 
         Ok(Self {
             x: x,
             y: y,
             width: width,
             height: height,
+            title: None,
+            recovering: false,
+            stopped: false,
+            latency: None,
+            terminal: None,
+            reaction: None,
+            hand_raised: false,
             video_handler: video_handler,
             bilinear_interpolater: bilinear_interpolater,
         })
     }
 
-    pub fn receive_stream(&mut self, stream: &Vec<u8>) -> Result<(), Error> {
+    pub fn resize(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.x = x;
+        self.y = y;
+        self.width = width;
+        self.height = height;
+        let (inner_width, inner_height) = Self::inner_size(width, height);
+        self.bilinear_interpolater = BilinearInterpolater::new(inner_width, inner_height * 2);
+    }
+
+    // the area inside the border and padding, which a crowded grid can squeeze to nothing
+    fn inner_size(width: u16, height: u16) -> (u16, u16) {
+        (
+            width.saturating_sub(2 * (Self::PADDING + 1)),
+            height.saturating_sub(2),
+        )
+    }
+
+    fn too_small(&self) -> bool {
+        let (inner_width, inner_height) = Self::inner_size(self.width, self.height);
+        inner_width == 0 || inner_height == 0
+    }
+
+    pub fn receive_stream(&mut self, stream: T::Stream) -> Result<(), Error> {
         let (width, height) = self.video_handler.receive_stream(stream)?;
         self.bilinear_interpolater
             .update_weights_if_needed(width, height);
@@ -76,10 +129,144 @@ impl<T: VideoHandler> VideoPanel<T> {
             .update_grayscale_buffer(&self.video_handler.rgb_buffer());
         Ok(())
     }
+
+    pub fn viewport(&self) -> (usize, usize, Renderer) {
+        let (width, height) = self.bilinear_interpolater.display_dimensions();
+        (width, height, Renderer::HalfBlock)
+    }
+
+    pub fn recovering(&self) -> bool {
+        self.recovering
+    }
+
+    pub fn set_recovering(&mut self, recovering: bool) {
+        self.recovering = recovering;
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // blanks the panel instead of leaving the last frame up
+    pub fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+        if stopped {
+            self.recovering = false;
+            self.latency = None;
+        }
+    }
+
+    pub fn set_title(&mut self, title: Option<String>) {
+        self.title = title;
+    }
+
+    pub fn set_latency(&mut self, latency: Option<LatencyBreakdown>) {
+        self.latency = latency;
+    }
+
+    pub fn terminal(&self) -> Option<&TerminalScreen> {
+        self.terminal.as_ref()
+    }
+
+    // starts showing a terminal if there wasn't one yet
+    pub fn terminal_mut(&mut self) -> &mut TerminalScreen {
+        self.terminal.get_or_insert_with(TerminalScreen::new)
+    }
+
+    pub fn clear_terminal(&mut self) {
+        self.terminal = None;
+    }
+
+    pub fn set_reaction(&mut self, emoji: &str) {
+        self.reaction = Some((emoji.to_owned(), Instant::now()));
+    }
+
+    // returns whether a reaction was taken down, in which case the panel needs drawing
+    pub fn expire_reaction(&mut self) -> bool {
+        match &self.reaction {
+            Some((_, shown)) if shown.elapsed() >= REACTION_DURATION => {
+                self.reaction = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn hand_raised(&self) -> bool {
+        self.hand_raised
+    }
+
+    // shown in the border until it's lowered
+    pub fn set_hand_raised(&mut self, hand_raised: bool) {
+        self.hand_raised = hand_raised;
+    }
+}
+
+impl<T: VideoHandler> VideoPanel<T> {
+    // drawn over whatever the panel shows, in the top right corner
+    fn draw_reaction(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        let emoji = match &self.reaction {
+            Some((emoji, shown)) if shown.elapsed() < REACTION_DURATION => emoji,
+            _ => return stdout.flush(),
+        };
+
+        let label = format!(" {} ", emoji);
+        let label_width = display_width(&label);
+        if label_width + 2 * (Self::PADDING + 1) > self.width {
+            return stdout.flush();
+        }
+        stdout
+            .queue(MoveTo(
+                self.x + self.width - Self::PADDING - 1 - label_width,
+                self.y + 1,
+            ))?
+            .queue(PrintStyledContent(label.on(Color::DarkGrey)))?;
+
+        stdout.flush()
+    }
+
+    fn draw_stopped(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        let inner_width = self.width - 2 * (Self::PADDING + 1);
+        for y in 1..self.height - 1 {
+            stdout
+                .queue(MoveTo(self.x + Self::PADDING + 1, self.y + y))?
+                .queue(Print(" ".repeat(inner_width as usize)))?;
+        }
+
+        let marker_width = STOPPED_MARKER.chars().count() as u16;
+        stdout
+            .queue(MoveTo(
+                self.x + (self.width.saturating_sub(marker_width)) / 2,
+                self.y + self.height / 2,
+            ))?
+            .queue(PrintStyledContent(STOPPED_MARKER.with(Color::DarkGrey)))?;
+
+        stdout.flush()
+    }
 }
 
 impl<T: VideoHandler> Drawable for VideoPanel<T> {
     fn draw(&self, stdout: &mut std::io::Stdout) -> Result<(), std::io::Error> {
+        if self.too_small() {
+            return Ok(());
+        }
+
+        if let Some(terminal) = &self.terminal {
+            terminal.draw(
+                stdout,
+                self.x + Self::PADDING + 1,
+                self.y + 1,
+                self.width - 2 * (Self::PADDING + 1),
+                self.height - 2,
+            )?;
+            return self.draw_reaction(stdout);
+        }
+
+        if self.stopped {
+            self.draw_stopped(stdout)?;
+            return self.draw_reaction(stdout);
+        }
+
         self.bilinear_interpolater
             .grouped_rows()
             .enumerate()
@@ -100,7 +287,31 @@ impl<T: VideoHandler> Drawable for VideoPanel<T> {
                 Ok(())
             })?;
 
-        stdout.flush()
+        let marker_width = RECOVERING_MARKER.chars().count() as u16;
+        if let Some(latency) = &self.latency {
+            // keep clear of the recovering marker
+            let max_width = self
+                .width
+                .saturating_sub(2 * (Self::PADDING + 1) + marker_width + 1);
+            let label = format_latency(latency)
+                .chars()
+                .take(max_width as usize)
+                .collect::<String>();
+            stdout
+                .queue(MoveTo(self.x + Self::PADDING + 1, self.y + self.height - 2))?
+                .queue(PrintStyledContent(label.with(Color::DarkGrey)))?;
+        }
+
+        if self.recovering {
+            stdout
+                .queue(MoveTo(
+                    self.x + self.width - Self::PADDING - 1 - marker_width,
+                    self.y + self.height - 2,
+                ))?
+                .queue(PrintStyledContent(RECOVERING_MARKER.with(Color::DarkGrey)))?;
+        }
+
+        self.draw_reaction(stdout)
     }
 
     fn x(&self) -> u16 {
@@ -118,6 +329,39 @@ impl<T: VideoHandler> Drawable for VideoPanel<T> {
     fn height(&self) -> u16 {
         self.height
     }
+
+    fn title(&self) -> Option<String> {
+        match (&self.title, self.hand_raised) {
+            (Some(title), true) => Some(format!("{} {}", HAND_MARKER, title)),
+            (None, true) => Some(String::from(HAND_MARKER)),
+            (title, false) => title.clone(),
+        }
+    }
+}
+
+fn format_latency(latency: &LatencyBreakdown) -> String {
+    format!(
+        "{} ms · cap {} · enc {} · net {} · dec {} · ren {}",
+        latency.total().as_millis(),
+        latency.capture.as_millis(),
+        latency.encode.as_millis(),
+        latency.network.as_millis(),
+        latency.decode.as_millis(),
+        latency.render.as_millis()
+    )
+}
+
+// emoji take two columns, and the code points that join or modify them take none
+fn display_width(text: &str) -> u16 {
+    text.chars()
+        .map(|c| match c {
+            '\u{200d}' | '\u{fe00}'..='\u{fe0f}' | '\u{1f3fb}'..='\u{1f3ff}' => 0,
+            // flags are made of two of these
+            '\u{1f1e6}'..='\u{1f1ff}' => 1,
+            c if c.is_ascii() => 1,
+            _ => 2,
+        })
+        .sum()
 }
 
 fn normalize(value: u8) -> u8 {
@@ -0,0 +1,80 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, PoisonError, Weak},
+};
+
+type FreeList = Mutex<Vec<Vec<u8>>>;
+
+// Hands out byte buffers that return to the pool once the last reference to them is dropped,
+// so that steady-state capture and encoding don't allocate.
+#[derive(Clone)]
+pub struct FramePool {
+    free: Arc<FreeList>,
+}
+
+impl FramePool {
+    // buffers beyond this are released instead of being kept around
+    const MAX_FREE: usize = 4;
+
+    pub fn new() -> Self {
+        Self {
+            free: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn acquire(&self, len: usize) -> PooledFrame {
+        let mut buffer = self
+            .free
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()
+            .unwrap_or_default();
+        buffer.resize(len, 0);
+
+        PooledFrame {
+            buffer: buffer,
+            pool: Arc::downgrade(&self.free),
+        }
+    }
+}
+
+pub struct PooledFrame {
+    buffer: Vec<u8>,
+    pool: Weak<FreeList>,
+}
+
+pub type SharedFrame = Arc<PooledFrame>;
+
+impl Deref for PooledFrame {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledFrame {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledFrame {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        let pool = match self.pool.upgrade() {
+            Some(pool) => pool,
+            None => return,
+        };
+
+        let mut free = pool.lock().unwrap_or_else(PoisonError::into_inner);
+        if free.len() < FramePool::MAX_FREE {
+            free.push(std::mem::take(&mut self.buffer));
+        }
+    }
+}
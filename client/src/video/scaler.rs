@@ -0,0 +1,60 @@
+pub(crate) fn fit_within(
+    (width, height): (usize, usize),
+    (max_width, max_height): (usize, usize),
+) -> (usize, usize) {
+    if width <= max_width && height <= max_height {
+        return (width & !1, height & !1);
+    }
+
+    let scale = (max_width as f32 / width as f32).min(max_height as f32 / height as f32);
+    let scaled_width = ((width as f32 * scale) as usize).max(2);
+    let scaled_height = ((height as f32 * scale) as usize).max(2);
+
+    // H.264 with 4:2:0 chroma subsampling needs even dimensions
+    (scaled_width & !1, scaled_height & !1)
+}
+
+pub(crate) fn downscale_rgb(
+    input: &[u8],
+    (input_width, input_height): (usize, usize),
+    output: &mut Vec<u8>,
+    (output_width, output_height): (usize, usize),
+) {
+    output.resize(output_width * output_height * 3, 0);
+
+    let x_ratio = input_width as f32 / output_width as f32;
+    let y_ratio = input_height as f32 / output_height as f32;
+    for y in 0..output_height {
+        let input_y = ((y as f32 * y_ratio) as usize).min(input_height - 1);
+        for x in 0..output_width {
+            let input_x = ((x as f32 * x_ratio) as usize).min(input_width - 1);
+            let from = (input_y * input_width + input_x) * 3;
+            let to = (y * output_width + x) * 3;
+            output[to..to + 3].copy_from_slice(&input[from..from + 3]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_without_scaling() {
+        assert_eq!(fit_within((640, 480), (1280, 720)), (640, 480));
+        assert_eq!(fit_within((641, 481), (1280, 720)), (640, 480));
+    }
+
+    #[test]
+    fn keeps_aspect_ratio() {
+        // limited by the height
+        assert_eq!(fit_within((1280, 720), (1280, 360)), (640, 360));
+        // limited by the width
+        assert_eq!(fit_within((1280, 720), (320, 720)), (320, 180));
+    }
+
+    #[test]
+    fn tiny_limits() {
+        assert_eq!(fit_within((1280, 720), (1, 1)), (2, 2));
+    }
+}
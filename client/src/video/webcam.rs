@@ -1,46 +1,246 @@
 use std::{
+    env,
     pin::Pin,
     sync::{
         Arc,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
     task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
 };
 
 use bytes::Bytes;
-use futures::Stream;
+use futures::{Stream, executor::block_on};
+use lazy_static::lazy_static;
 use nokhwa::{
     Camera,
     pixel_format::RgbFormat,
     utils::{CameraIndex, RequestedFormat, RequestedFormatType},
 };
-use openh264::formats::{RgbSliceU8, YUVBuffer};
-use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+use openh264::{
+    OpenH264API,
+    encoder::{BitRate, Encoder, EncoderConfig, FrameRate, RateControlMode},
+    formats::{RgbSliceU8, YUVBuffer},
+};
+use tokio::sync::watch;
 use tracing::info;
 
-use crate::video::encoding::get_prefix_code;
+use crate::video::{
+    bitrate::EncoderTarget,
+    channel::{FrameReceiver, FrameSender, frame_channel},
+    pool::{FramePool, SharedFrame},
+    scaler::{downscale_rgb, fit_within},
+};
+
+lazy_static! {
+    static ref FRAME_RATE: f32 = env::var("FRAME_RATE")
+        .ok()
+        .and_then(|frame_rate| frame_rate.parse::<f32>().ok())
+        .filter(|frame_rate| *frame_rate > 0.0)
+        .unwrap_or(30.0);
+}
+
+// the terminal only ever needs the latest picture, while the peer gets a little slack
+const LOCAL_FRAME_CAPACITY: usize = 1;
+const CAPTURED_FRAME_CAPACITY: usize = 1;
+const ENCODED_FRAME_CAPACITY: usize = 2;
+
+struct CapturedFrame {
+    rgb_frame: SharedFrame,
+    captured_at: SystemTime,
+    capture: Duration,
+}
+
+// Encodes on its own thread so that a slow encode doesn't hold up capture and the local preview
+struct EncoderWorker {
+    frames: FrameReceiver<CapturedFrame>,
+    peer_sender: FrameSender<EncodedFrame>,
+    force_keyframe: Arc<AtomicBool>,
+    target_receiver: watch::Receiver<EncoderTarget>,
+    viewport_limit_receiver: watch::Receiver<ViewportLimit>,
+}
+
+impl EncoderWorker {
+    fn run(mut self, camera_dimensions: (usize, usize)) {
+        info!("started encoder thread");
+
+        let encoded_pool = FramePool::new();
+        let mut scaled_buffer = Vec::new();
+
+        let mut target = *self.target_receiver.borrow_and_update();
+        let mut viewport_limit = *self.viewport_limit_receiver.borrow_and_update();
+        let mut encode_dimensions = target_dimensions(camera_dimensions, &target, viewport_limit);
+        let mut yuv_buffer = YUVBuffer::new(encode_dimensions.0, encode_dimensions.1);
+
+        let mut h264_encoder = match create_encoder(&target) {
+            Ok(h264_encoder) => h264_encoder,
+            Err(e) => {
+                info!("unable to create h264 encoder: {e}");
+                return;
+            }
+        };
+        let mut last_encode: Option<SystemTime> = None;
+        let capture_interval = Duration::from_secs_f32(1.0 / *FRAME_RATE);
+
+        // only ever picks up the newest frame, stale ones are dropped by the channel
+        while let Some(frame) = block_on(self.frames.recv()) {
+            if self.target_receiver.has_changed().unwrap_or(false) {
+                target = *self.target_receiver.borrow_and_update();
+                info!("updating encoder target: {:?}", target);
+
+                match create_encoder(&target) {
+                    Ok(encoder) => h264_encoder = encoder,
+                    Err(e) => info!("unable to recreate h264 encoder: {e}"),
+                }
+            }
+
+            if self.viewport_limit_receiver.has_changed().unwrap_or(false) {
+                viewport_limit = *self.viewport_limit_receiver.borrow_and_update();
+                info!("updating viewport limit: {:?}", viewport_limit);
+            }
+
+            // the encoder re-initializes itself whenever the input dimensions change
+            let dimensions = target_dimensions(camera_dimensions, &target, viewport_limit);
+            if dimensions != encode_dimensions {
+                encode_dimensions = dimensions;
+                yuv_buffer = YUVBuffer::new(dimensions.0, dimensions.1);
+            }
+
+            let frame_interval = Duration::from_secs_f32(1.0 / target.frame_rate.min(*FRAME_RATE));
+            let since_last_encode = last_encode
+                .and_then(|last_encode| frame.captured_at.duration_since(last_encode).ok());
+            if since_last_encode
+                .is_some_and(|elapsed| elapsed + capture_interval / 2 < frame_interval)
+            {
+                continue;
+            }
+            last_encode = Some(frame.captured_at);
+            let encode_started = Instant::now();
+
+            let slice = if encode_dimensions == camera_dimensions {
+                RgbSliceU8::new(&frame.rgb_frame, camera_dimensions)
+            } else {
+                downscale_rgb(
+                    &frame.rgb_frame,
+                    camera_dimensions,
+                    &mut scaled_buffer,
+                    encode_dimensions,
+                );
+                RgbSliceU8::new(&scaled_buffer, encode_dimensions)
+            };
+            yuv_buffer.read_rgb8(slice);
+
+            if self.force_keyframe.swap(false, Ordering::AcqRel) {
+                h264_encoder.force_intra_frame();
+            }
+            let bit_stream = match h264_encoder.encode(&yuv_buffer) {
+                Ok(bit_stream) => bit_stream,
+                Err(e) => {
+                    info!("failed to enocde to h264: {e}");
+                    continue;
+                }
+            };
+            let mut encoded = encoded_pool.acquire(0);
+            bit_stream.write_vec(&mut encoded);
+            if encoded.is_empty() {
+                // the encoder skipped this frame to stay within its bitrate
+                continue;
+            }
+
+            // all NAL units of an access unit go out as a single sample so that the
+            // packetizer only sets the marker bit on the last packet of the frame
+            let encoded_frame = EncodedFrame {
+                data: Bytes::from_owner(encoded),
+                timestamp: frame.captured_at,
+                duration: since_last_encode.unwrap_or(frame_interval),
+                capture: frame.capture,
+                encode: encode_started.elapsed(),
+            };
+            match self.peer_sender.send(encoded_frame) {
+                // the peer can't decode past the dropped frame, so start over from a keyframe
+                Ok(Some(_)) => self.force_keyframe.store(true, Ordering::Release),
+                Ok(None) => {}
+                Err(e) => info!("failed to send encoded frame: {e}"),
+            }
+        }
+    }
+}
+
+pub type ViewportLimit = Option<(usize, usize)>;
+
+pub struct EncodedFrame {
+    pub data: Bytes,
+    pub timestamp: SystemTime,
+    pub duration: Duration,
+    pub capture: Duration,
+    pub encode: Duration,
+}
 
 pub struct Webcam {
     broadcast_toggle: Arc<AtomicBool>,
-    peer_receiver: Option<UnboundedReceiver<Bytes>>,
+    force_keyframe: Arc<AtomicBool>,
+    encoder_target: Arc<watch::Sender<EncoderTarget>>,
+    viewport_limit: Arc<watch::Sender<ViewportLimit>>,
+    peer_receiver: Option<FrameReceiver<EncodedFrame>>,
+    skipped_frames: Arc<AtomicU64>,
+    dropped_frames: Arc<AtomicU64>,
+}
+
+fn create_encoder(target: &EncoderTarget) -> Result<Encoder, openh264::Error> {
+    let config = EncoderConfig::new()
+        .bitrate(BitRate::from_bps(target.bitrate))
+        .max_frame_rate(FrameRate::from_hz(target.frame_rate))
+        .rate_control_mode(RateControlMode::Bitrate);
+
+    Encoder::with_api_config(OpenH264API::from_source(), config)
+}
+
+fn target_dimensions(
+    camera_dimensions: (usize, usize),
+    target: &EncoderTarget,
+    viewport_limit: ViewportLimit,
+) -> (usize, usize) {
+    let dimensions = fit_within(camera_dimensions, (target.max_width, target.max_height));
+    match viewport_limit {
+        // scaled as a whole, so the picture keeps its aspect ratio
+        Some(viewport_limit) => fit_within(dimensions, viewport_limit),
+        None => dimensions,
+    }
 }
 
 impl Webcam {
     pub fn new() -> Self {
         let broadcast_toggle = Arc::new(AtomicBool::new(false));
+        let (encoder_target, _) = watch::channel(EncoderTarget::default());
+        let (viewport_limit, _) = watch::channel(None);
 
         Self {
             broadcast_toggle: broadcast_toggle,
+            force_keyframe: Arc::new(AtomicBool::new(false)),
+            encoder_target: Arc::new(encoder_target),
+            viewport_limit: Arc::new(viewport_limit),
             peer_receiver: None,
+            skipped_frames: Arc::new(AtomicU64::new(0)),
+            dropped_frames: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    pub fn start_webcam(&mut self) -> UnboundedReceiver<Vec<u8>> {
-        let (local_sender, local_receiver) = unbounded_channel();
-        let (peer_sender, peer_receiver) = unbounded_channel();
+    pub fn start_webcam(&mut self) -> FrameReceiver<SharedFrame> {
+        let (local_sender, local_receiver) = frame_channel(LOCAL_FRAME_CAPACITY);
+        let (capture_sender, capture_receiver) = frame_channel(CAPTURED_FRAME_CAPACITY);
+        let (peer_sender, peer_receiver) = frame_channel(ENCODED_FRAME_CAPACITY);
+        self.skipped_frames = capture_receiver.dropped_counter();
+        self.dropped_frames = peer_receiver.dropped_counter();
         self.peer_receiver = Some(peer_receiver);
 
         let broadcast_toggle = self.broadcast_toggle.clone();
+        let encoder = EncoderWorker {
+            frames: capture_receiver,
+            peer_sender: peer_sender,
+            force_keyframe: self.force_keyframe.clone(),
+            target_receiver: self.encoder_target.subscribe(),
+            viewport_limit_receiver: self.viewport_limit.subscribe(),
+        };
         std::thread::spawn(move || {
             info!("started webcam thread");
 
@@ -65,19 +265,20 @@ impl Webcam {
             let input_buffer_size = input_width * input_height * 3;
             let camera_dimensions = (input_width, input_height);
 
-            let mut rgb_buffer = vec![0; input_buffer_size];
-            let mut yuv_buffer = YUVBuffer::new(input_width, input_height);
+            std::thread::spawn(move || encoder.run(camera_dimensions));
 
-            let mut h264_encoder = match openh264::encoder::Encoder::new() {
-                Ok(h264_encoder) => h264_encoder,
-                Err(e) => {
-                    info!("unable to create h264 encoder: {e}");
-                    return;
-                }
-            };
-            let mut h264_encoded_buffer = Vec::new();
+            let rgb_pool = FramePool::new();
+            let capture_interval = Duration::from_secs_f32(1.0 / *FRAME_RATE);
+            let mut next_capture = Instant::now();
 
             loop {
+                let now = Instant::now();
+                if next_capture > now {
+                    std::thread::sleep(next_capture - now);
+                }
+                // don't try to catch up on frames if we've fallen behind
+                next_capture = (next_capture + capture_interval).max(Instant::now());
+
                 let frame = match camera.frame() {
                     Ok(frame) => frame,
                     Err(e) => {
@@ -86,45 +287,34 @@ impl Webcam {
                     }
                 };
 
-                if let Err(e) = frame.decode_image_to_buffer::<RgbFormat>(&mut rgb_buffer) {
+                let captured_at = SystemTime::now();
+                let capture_started = Instant::now();
+
+                let mut rgb_frame = rgb_pool.acquire(input_buffer_size);
+                if let Err(e) = frame.decode_image_to_buffer::<RgbFormat>(&mut rgb_frame) {
                     info!("failed to decode_image_to_buffer: {}", e);
                     continue;
                 }
+                let capture_duration = capture_started.elapsed();
 
-                if let Err(e) = local_sender.send(rgb_buffer.clone()) {
+                // the local preview and the encoder share the same frame
+                let rgb_frame = Arc::new(rgb_frame);
+                if let Err(e) = local_sender.send(rgb_frame.clone()) {
                     info!("unable to send rgb_buffer to local video: {}", e);
                 }
                 if !broadcast_toggle.load(Ordering::Acquire) {
                     continue;
                 }
 
-                let slice = RgbSliceU8::new(&rgb_buffer, camera_dimensions);
-                yuv_buffer.read_rgb8(slice);
-
-                h264_encoder.force_intra_frame();
-                let bit_stream = match h264_encoder.encode(&yuv_buffer) {
-                    Ok(bit_stream) => bit_stream,
-                    Err(e) => {
-                        info!("failed to enocde to h264: {e}");
-                        continue;
-                    }
+                // if the encoder is still busy, the frame it hasn't picked up yet is replaced
+                let captured_frame = CapturedFrame {
+                    rgb_frame: rgb_frame,
+                    captured_at: captured_at,
+                    capture: capture_duration,
                 };
-                h264_encoded_buffer.clear();
-                bit_stream.write_vec(&mut h264_encoded_buffer);
-
-                openh264::nal_units(&h264_encoded_buffer)
-                    .map(Bytes::copy_from_slice)
-                    .for_each(|nal_unit| {
-                        // TODO: remove log
-                        match get_prefix_code(&nal_unit) {
-                            Ok(nal_type) => info!("sending nal type: {}", nal_type as u8),
-                            Err(e) => info!("{}", e),
-                        }
-
-                        if let Err(e) = peer_sender.send(nal_unit) {
-                            info!("failed to send nal unit: {e}");
-                        };
-                    });
+                if let Err(e) = capture_sender.send(captured_frame) {
+                    info!("unable to send frame to encoder: {}", e);
+                }
             }
         });
 
@@ -132,12 +322,42 @@ impl Webcam {
     }
 
     pub fn start_broadcast(&mut self) {
+        // anything left over from an earlier broadcast is stale, and peers need a keyframe first
+        if let Some(peer_receiver) = &mut self.peer_receiver {
+            peer_receiver.clear();
+        }
+        self.force_keyframe.store(true, Ordering::Release);
         self.broadcast_toggle.store(true, Ordering::Relaxed);
     }
+
+    pub fn stop_broadcast(&mut self) {
+        self.broadcast_toggle.store(false, Ordering::Relaxed);
+    }
+
+    pub fn force_keyframe(&self) -> Arc<AtomicBool> {
+        self.force_keyframe.clone()
+    }
+
+    pub fn encoder_target(&self) -> Arc<watch::Sender<EncoderTarget>> {
+        self.encoder_target.clone()
+    }
+
+    pub fn viewport_limit(&self) -> Arc<watch::Sender<ViewportLimit>> {
+        self.viewport_limit.clone()
+    }
+
+    // frames the encoder didn't get to before a newer one was captured
+    pub fn skipped_frames(&self) -> Arc<AtomicU64> {
+        self.skipped_frames.clone()
+    }
+
+    pub fn dropped_frames(&self) -> Arc<AtomicU64> {
+        self.dropped_frames.clone()
+    }
 }
 
 impl Stream for Webcam {
-    type Item = Bytes;
+    type Item = EncodedFrame;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let receiver = match &mut self.peer_receiver {
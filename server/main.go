@@ -19,8 +19,15 @@ func main() {
 	port := ":" + os.Getenv("PORT")
 	jwtSecret := os.Getenv("JWT_SECRET")
 
+	iceConfig := room.NewIceConfig(
+		os.Getenv("ICE_SERVERS"),
+		os.Getenv("TURN_SECRET"),
+		os.Getenv("TURN_USERNAME"),
+		os.Getenv("TURN_CREDENTIAL"),
+	)
+
 	sessionManager := room.NewSessionManager([]byte(jwtSecret))
-	roomManager := room.NewRoomManager(&sessionManager)
+	roomManager := room.NewRoomManager(&sessionManager, &iceConfig)
 
 	mux := http.NewServeMux()
 	mux.HandleFunc("POST /create", func(w http.ResponseWriter, r *http.Request) {
@@ -31,6 +38,10 @@ func main() {
 		log.Println("[router] POST /join/{room}")
 		roomManager.HandleJoinRoom(w, r)
 	})
+	mux.HandleFunc("GET /ice", func(w http.ResponseWriter, r *http.Request) {
+		log.Println("[router] GET /ice")
+		roomManager.HandleIceServers(w, r)
+	})
 	mux.HandleFunc("GET /ws", func(w http.ResponseWriter, r *http.Request) {
 		log.Println("[router] GET /ws")
 		roomManager.HandleWebSocket(w, r)
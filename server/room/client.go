@@ -10,6 +10,7 @@ import (
 
 type RoomClient struct {
 	username string
+	session  string
 	conn     *websocket.Conn
 	room     *Room
 	send     chan []byte
@@ -29,15 +30,26 @@ func (r *RoomClient) readPump() {
 
 		if signaling.IsSignalMessage(data) {
 			var message signaling.SignalMessage
-			if err := json.Unmarshal(data, &message); err == nil {
-				log.Printf("[client %s] sending %s message with payload %s", r.username, message.Type, message.Payload)
+			if err := json.Unmarshal(data, &message); err != nil {
+				continue
 			}
+			log.Printf("[client %s] sending %s message with payload %s", r.username, message.Type, message.Payload)
 
-			r.room.broadcastToAllExcept(data, r)
+			// messages for a single peer in a mesh call don't need to reach everyone
+			if message.To != nil {
+				r.room.sendTo(data, message.To)
+			} else {
+				r.room.broadcastToAllExcept(data, r)
+			}
 		} else {
+			// only some messages have content, e.g. raising a hand doesn't
 			var message RoomMessage
 			if err := json.Unmarshal(data, &message); err == nil {
-				log.Printf("[client %s] sending %s message with payload %s", r.username, message.Type, *message.Content)
+				if message.Content != nil {
+					log.Printf("[client %s] sending %s message with payload %s", r.username, message.Type, *message.Content)
+				} else {
+					log.Printf("[client %s] sending %s message", r.username, message.Type)
+				}
 			}
 
 			r.room.broadcastToAll(data)
@@ -0,0 +1,80 @@
+package room
+
+import (
+	"crypto/hmac"
+	"crypto/sha1"
+	"encoding/base64"
+	"fmt"
+	"strings"
+	"time"
+)
+
+// TURN credentials handed out with a shared secret are only valid for this long
+const turnCredentialLifetime = 12 * time.Hour
+
+type IceServer struct {
+	URLs       []string `json:"urls"`
+	Username   string   `json:"username,omitempty"`
+	Credential string   `json:"credential,omitempty"`
+}
+
+type IceConfig struct {
+	stunURLs       []string
+	turnURLs       []string
+	turnSecret     []byte
+	turnUsername   string
+	turnCredential string
+}
+
+// urls is a comma separated list of stun: and turn: urls. With a secret, short-lived TURN
+// credentials are issued following the TURN REST API (coturn's use-auth-secret), otherwise
+// the static username and credential are used
+func NewIceConfig(urls string, turnSecret string, turnUsername string, turnCredential string) IceConfig {
+	config := IceConfig{
+		turnSecret:     []byte(turnSecret),
+		turnUsername:   turnUsername,
+		turnCredential: turnCredential,
+	}
+
+	for _, url := range strings.Split(urls, ",") {
+		url = strings.TrimSpace(url)
+		switch {
+		case url == "":
+			continue
+		case strings.HasPrefix(url, "turn:") || strings.HasPrefix(url, "turns:"):
+			config.turnURLs = append(config.turnURLs, url)
+		default:
+			config.stunURLs = append(config.stunURLs, url)
+		}
+	}
+	return config
+}
+
+func (c *IceConfig) iceServers(roomId string) []IceServer {
+	servers := []IceServer{}
+	if len(c.stunURLs) > 0 {
+		servers = append(servers, IceServer{URLs: c.stunURLs})
+	}
+	if len(c.turnURLs) > 0 {
+		username, credential := c.turnUsername, c.turnCredential
+		if len(c.turnSecret) > 0 {
+			username, credential = c.temporaryCredentials(roomId)
+		}
+		servers = append(servers, IceServer{
+			URLs:       c.turnURLs,
+			Username:   username,
+			Credential: credential,
+		})
+	}
+	return servers
+}
+
+func (c *IceConfig) temporaryCredentials(roomId string) (string, string) {
+	expiry := time.Now().Add(turnCredentialLifetime).Unix()
+	username := fmt.Sprintf("%d:%s", expiry, roomId)
+
+	mac := hmac.New(sha1.New, c.turnSecret)
+	mac.Write([]byte(username))
+	credential := base64.StdEncoding.EncodeToString(mac.Sum(nil))
+	return username, credential
+}
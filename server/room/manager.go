@@ -15,6 +15,7 @@ type RoomManager struct {
 	rooms          map[string]*Room
 	mu             sync.Mutex
 	sessionManager *SessionManager
+	iceConfig      *IceConfig
 }
 
 var (
@@ -24,10 +25,11 @@ var (
 	encoder, _ = sqids.New(sqids.Options{MinLength: 6})
 )
 
-func NewRoomManager(sessionManager *SessionManager) *RoomManager {
+func NewRoomManager(sessionManager *SessionManager, iceConfig *IceConfig) *RoomManager {
 	return &RoomManager{
 		rooms:          make(map[string]*Room),
 		sessionManager: sessionManager,
+		iceConfig:      iceConfig,
 	}
 }
 
@@ -80,6 +82,23 @@ func (m *RoomManager) HandleJoinRoom(w http.ResponseWriter, r *http.Request) {
 	}
 }
 
+func (m *RoomManager) HandleIceServers(w http.ResponseWriter, r *http.Request) {
+	token := r.URL.Query().Get("token")
+	roomId, err := m.sessionManager.validateToken(token)
+	if err != nil {
+		http.Error(w, "invalid token", http.StatusUnauthorized)
+		return
+	}
+
+	body := map[string][]IceServer{
+		"iceServers": m.iceConfig.iceServers(roomId),
+	}
+
+	if err := json.NewEncoder(w).Encode(body); err != nil {
+		http.Error(w, "unable to get ice servers", http.StatusInternalServerError)
+	}
+}
+
 func (m *RoomManager) HandleWebSocket(w http.ResponseWriter, r *http.Request) {
 	token := r.URL.Query().Get("token")
 	roomId, err := m.sessionManager.validateToken(token)
@@ -92,6 +111,7 @@ func (m *RoomManager) HandleWebSocket(w http.ResponseWriter, r *http.Request) {
 	if username == "" {
 		username = "???"
 	}
+	var session = r.URL.Query().Get("session")
 
 	room, ok := m.rooms[roomId]
 	if !ok {
@@ -107,6 +127,7 @@ func (m *RoomManager) HandleWebSocket(w http.ResponseWriter, r *http.Request) {
 
 	client := &RoomClient{
 		username: username,
+		session:  session,
 		conn:     conn,
 		send:     make(chan []byte, 256),
 		room:     room,
@@ -3,13 +3,17 @@ package room
 type RoomMessage struct {
 	Type     RoomMessageType `json:"type"`
 	Username *string         `json:"username,omitempty"`
+	Session  *string         `json:"session,omitempty"`
 	Content  *string         `json:"content,omitempty"`
+	Raised   *bool           `json:"raised,omitempty"`
 }
 
 type RoomMessageType string
 
 const (
-	RoomMessageTypeChat  RoomMessageType = "chat"
-	RoomMessageTypeJoin  RoomMessageType = "join"
-	RoomMessageTypeLeave RoomMessageType = "leave"
+	RoomMessageTypeChat     RoomMessageType = "chat"
+	RoomMessageTypeJoin     RoomMessageType = "join"
+	RoomMessageTypeLeave    RoomMessageType = "leave"
+	RoomMessageTypeReaction RoomMessageType = "reaction"
+	RoomMessageTypeHand     RoomMessageType = "hand"
 )
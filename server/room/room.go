@@ -43,6 +43,7 @@ func (r *Room) run() {
 			joinMessage := RoomMessage{
 				Type:     RoomMessageTypeJoin,
 				Username: &client.username,
+				Session:  &client.session,
 				Content:  nil,
 			}
 			serialized, err := json.Marshal(&joinMessage)
@@ -61,6 +62,7 @@ func (r *Room) run() {
 			leaveMessage := RoomMessage{
 				Type:     RoomMessageTypeLeave,
 				Username: &client.username,
+				Session:  &client.session,
 				Content:  nil,
 			}
 			serialized, err := json.Marshal(&leaveMessage)
@@ -85,6 +87,14 @@ func (r *Room) broadcastToAllExcept(data []byte, exclude *RoomClient) {
 	}
 }
 
+func (r *Room) sendTo(data []byte, participant *signaling.Participant) {
+	for client := range r.clients {
+		if participant.Matches(client.username, client.session) {
+			client.send <- data
+		}
+	}
+}
+
 func (r *Room) OnCandidateMessage(message *signaling.SignalMessage) {
 	log.Printf("[room %s] received candidate with payload: %s", r.roomId, message.Payload)
 
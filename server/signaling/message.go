@@ -6,15 +6,42 @@ import (
 
 type SignalMessage struct {
 	Type    SignalMessageType `json:"type"`
+	From    *Participant      `json:"from,omitempty"`
+	To      *Participant      `json:"to,omitempty"`
 	Payload any               `json:"payload"`
 }
 
+// A client in a room. The session tells apart clients that share a username.
+type Participant struct {
+	Username string `json:"username"`
+	Session  string `json:"session,omitempty"`
+}
+
+// Also accepts a bare username, which is what clients sent before sessions were added
+func (p *Participant) UnmarshalJSON(data []byte) error {
+	var username string
+	if err := json.Unmarshal(data, &username); err == nil {
+		*p = Participant{Username: username}
+		return nil
+	}
+
+	type participant Participant
+	return json.Unmarshal(data, (*participant)(p))
+}
+
+func (p *Participant) Matches(username string, session string) bool {
+	return p.Username == username && (p.Session == "" || p.Session == session)
+}
+
 type SignalMessageType string
 
 const (
-	SignalMessageTypeOffer     SignalMessageType = "offer"
-	SignalMessageTypeAnswer    SignalMessageType = "answer"
-	SignalMessageTypeCandidate SignalMessageType = "candidate"
+	SignalMessageTypeOffer       SignalMessageType = "offer"
+	SignalMessageTypeAnswer      SignalMessageType = "answer"
+	SignalMessageTypeCandidate   SignalMessageType = "candidate"
+	SignalMessageTypeViewport    SignalMessageType = "viewport"
+	SignalMessageTypeStopped     SignalMessageType = "stopped"
+	SignalMessageTypeRenegotiate SignalMessageType = "renegotiate"
 )
 
 func IsSignalMessage(data []byte) bool {
@@ -26,7 +53,10 @@ func IsSignalMessage(data []byte) bool {
 	switch probe.Type {
 	case string(SignalMessageTypeOffer),
 		string(SignalMessageTypeAnswer),
-		string(SignalMessageTypeCandidate):
+		string(SignalMessageTypeCandidate),
+		string(SignalMessageTypeViewport),
+		string(SignalMessageTypeStopped),
+		string(SignalMessageTypeRenegotiate):
 		return true
 	default:
 		return false